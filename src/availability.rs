@@ -0,0 +1,117 @@
+use crate::tiers::{Tier, Tiers};
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use std::fmt;
+
+/// One row of a funds availability policy: deposits of at least `from` stay
+/// pending until `transactions` more transactions have been processed or
+/// `seconds` have passed, whichever comes first. Several rows with different
+/// `from` amounts make up tiers, and the highest tier the amount reaches
+/// applies. A tier with neither delay makes deposits available at once.
+#[derive(Debug, Deserialize, Clone, PartialEq, Eq)]
+pub struct HoldRule {
+    pub from: Option<Decimal>,
+    pub transactions: Option<u64>,
+    pub seconds: Option<u64>,
+}
+
+impl HoldRule {
+    fn delays(&self) -> bool {
+        self.transactions.is_some() || self.seconds.is_some()
+    }
+}
+
+impl Tier for HoldRule {
+    fn threshold(&self) -> Decimal {
+        self.from.unwrap_or_default()
+    }
+}
+
+/// A hold rule with a negative `from` amount.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidHoldRule(pub HoldRule);
+
+impl fmt::Display for InvalidHoldRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0.from {
+            Some(from) => write!(f, "hold rule: from can't be negative, got {from}"),
+            None => write!(f, "invalid hold rule"),
+        }
+    }
+}
+
+impl std::error::Error for InvalidHoldRule {}
+
+/// How long deposits stay pending before they can be spent. Without any
+/// rule, deposits are available at once.
+#[derive(Debug, Default, Clone)]
+pub struct FundsAvailability {
+    tiers: Tiers<HoldRule>,
+}
+
+impl FundsAvailability {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a rule, replacing any earlier one for the same tier.
+    pub fn insert(&mut self, rule: HoldRule) -> Result<(), InvalidHoldRule> {
+        if rule.from.is_some_and(|from| from < Decimal::ZERO) {
+            return Err(InvalidHoldRule(rule));
+        }
+        self.tiers.insert(rule);
+        Ok(())
+    }
+
+    /// The rule that holds a deposit of `amount`, if it is held at all.
+    pub fn hold(&self, amount: Decimal) -> Option<&HoldRule> {
+        let rule = self.tiers.get(amount)?;
+        rule.delays().then_some(rule)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+    use rust_decimal_macros::dec;
+
+    fn rule(from: Option<Decimal>, transactions: Option<u64>) -> HoldRule {
+        HoldRule {
+            from,
+            transactions,
+            seconds: None,
+        }
+    }
+
+    #[rstest]
+    #[case::no_rule(vec![], dec!(100), None)]
+    #[case::single(vec![rule(None, Some(3))], dec!(100), Some(3))]
+    #[case::lower_tier(vec![rule(None, Some(1)), rule(Some(dec!(1000)), Some(5))], dec!(999), Some(1))]
+    #[case::upper_tier(vec![rule(Some(dec!(1000)), Some(5)), rule(None, Some(1))], dec!(1000), Some(5))]
+    #[case::below_first_tier(vec![rule(Some(dec!(1000)), Some(5))], dec!(999), None)]
+    #[case::immediate_tier(vec![rule(None, None), rule(Some(dec!(1000)), Some(5))], dec!(10), None)]
+    fn test_hold_tiers(
+        #[case] rules: Vec<HoldRule>,
+        #[case] amount: Decimal,
+        #[case] expected: Option<u64>,
+    ) {
+        let mut policy = FundsAvailability::new();
+        for rule in rules {
+            policy.insert(rule).unwrap();
+        }
+        assert_eq!(
+            policy.hold(amount).and_then(|rule| rule.transactions),
+            expected
+        );
+        let negative = rule(Some(dec!(-1)), None);
+        assert_eq!(
+            policy.insert(negative.clone()),
+            Err(InvalidHoldRule(negative.clone()))
+        );
+        assert_eq!(
+            InvalidHoldRule(negative).to_string(),
+            "hold rule: from can't be negative, got -1"
+        );
+    }
+}
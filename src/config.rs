@@ -0,0 +1,140 @@
+use crate::{FeeSchedule, FundsAvailability, OverdraftLimits, RiskRules};
+use serde::Serialize;
+
+/// What to do when a deposit or withdrawal reuses a tx id that is already in
+/// the transaction history.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum DuplicatePolicy {
+    /// Reject the later transaction.
+    #[default]
+    Reject,
+    /// Apply the later transaction and let it replace the earlier one as the
+    /// target of future disputes. Rejected while the earlier one is disputed,
+    /// so its held funds can still be released.
+    LastWins,
+    /// Apply the later transaction but keep the earlier one as the target of
+    /// future disputes.
+    FirstWins,
+}
+
+/// How a dispute treats the disputed transaction, configured separately for
+/// deposits and withdrawals.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum DisputePolicy {
+    /// Reject disputes on this kind of transaction.
+    Disallow,
+    /// Move the amount from available to held until the dispute is settled.
+    Hold,
+    /// Credit the amount back into held until the dispute is settled, and
+    /// release it to available on chargeback. For deposits this is the same as
+    /// `Hold`, since the disputed funds never left the account.
+    ReversalCredit,
+}
+
+/// What to do when a dispute would hold more than the client has available,
+/// e.g. a deposit that was mostly withdrawn before being disputed.
+#[derive(Debug, Serialize, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum ShortfallPolicy {
+    /// Reject the dispute.
+    Reject,
+    /// Hold the full amount, let available go negative and flag the account.
+    #[default]
+    AllowNegative,
+    /// Hold only what is available and record the rest as a shortfall.
+    CapHold,
+}
+
+/// What to do when a transaction would overflow a balance.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum OverflowPolicy {
+    /// Clamp the balance at `Decimal::MAX`/`MIN` and apply the transaction,
+    /// reporting it as [`Event::Saturated`](crate::Event::Saturated).
+    #[default]
+    Saturate,
+    /// Reject the transaction with [`TxError::Overflow`](crate::TxError::Overflow).
+    Reject,
+    /// Like `Reject`, but callers are expected to stop processing. The engine
+    /// itself can't abort a run, so this is left to whoever drives it.
+    Abort,
+}
+
+/// When the fee charged on a deposit or withdrawal is given back because the
+/// transaction was disputed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum FeeRefundPolicy {
+    /// Keep the fee whatever happens to the transaction.
+    #[default]
+    Never,
+    /// Refund the fee when the transaction is first disputed, and keep it
+    /// refunded even if the dispute is resolved.
+    OnDispute,
+    /// Refund the fee when the transaction is charged back.
+    OnChargeback,
+}
+
+/// Tunable behaviour of an [`Engine`](crate::Engine). See each policy for its
+/// default.
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub duplicates: DuplicatePolicy,
+    pub deposit_disputes: DisputePolicy,
+    pub withdrawal_disputes: DisputePolicy,
+    pub shortfall: ShortfallPolicy,
+    pub overflow: OverflowPolicy,
+    /// How long after a transaction a dispute may be opened on it, in the
+    /// unit of [`Input::timestamp`](crate::Input::timestamp). Only enforced
+    /// when both the transaction and the dispute carry a timestamp.
+    pub dispute_window: Option<u64>,
+    /// How long a dispute may stay open before it is resolved automatically,
+    /// reported as [`Event::DisputeExpired`](crate::Event::DisputeExpired).
+    /// Only disputes opened with a timestamp expire.
+    pub dispute_timeout: Option<u64>,
+    /// How many times a tx may be disputed again after being resolved. `None`
+    /// allows any number of re-disputes.
+    pub redispute_limit: Option<u32>,
+    /// How long an authorization may hold funds before it is voided
+    /// automatically, reported as [`Event::AuthExpired`](crate::Event::AuthExpired).
+    /// Only authorizations with a timestamp expire this way.
+    pub auth_expiry: Option<u64>,
+    /// How many later transactions an authorization may stay open for before
+    /// it is voided automatically.
+    pub auth_expiry_txns: Option<u64>,
+    pub fees: FeeSchedule,
+    pub fee_refunds: FeeRefundPolicy,
+    /// How far each client may overdraw their balances with withdrawals,
+    /// transfers and fees.
+    pub overdrafts: OverdraftLimits,
+    /// How long deposits stay pending before they are available.
+    pub funds_availability: FundsAvailability,
+    /// Velocity and cooldown rules every transaction is screened against
+    /// before it is applied.
+    pub risk: RiskRules,
+    /// Whether to keep a double-entry journal, reporting every balance
+    /// movement as an [`Event::Journal`](crate::Event::Journal) entry, which
+    /// a [`TrialBalance`](crate::TrialBalance) can sum up.
+    pub journal: bool,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            duplicates: DuplicatePolicy::default(),
+            deposit_disputes: DisputePolicy::Hold,
+            withdrawal_disputes: DisputePolicy::Hold,
+            shortfall: ShortfallPolicy::default(),
+            overflow: OverflowPolicy::default(),
+            dispute_window: None,
+            dispute_timeout: None,
+            redispute_limit: None,
+            auth_expiry: None,
+            auth_expiry_txns: None,
+            fees: FeeSchedule::default(),
+            fee_refunds: FeeRefundPolicy::default(),
+            overdrafts: OverdraftLimits::default(),
+            funds_availability: FundsAvailability::default(),
+            risk: RiskRules::default(),
+            journal: false,
+        }
+    }
+}
@@ -0,0 +1,84 @@
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+use std::str::FromStr;
+
+/// A three-letter currency code such as `EUR`, stored inline so it is cheap
+/// to copy and hash. Codes are case-insensitive on input and kept uppercase.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Currency([u8; 3]);
+
+impl Currency {
+    pub fn as_str(&self) -> &str {
+        // only ascii letters get past `from_str`
+        std::str::from_utf8(&self.0).expect("currency codes are ascii")
+    }
+}
+
+/// A currency code that isn't exactly three ascii letters.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidCurrency(pub String);
+
+impl fmt::Display for InvalidCurrency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "invalid currency code {:?}, expected three letters",
+            self.0
+        )
+    }
+}
+
+impl std::error::Error for InvalidCurrency {}
+
+impl FromStr for Currency {
+    type Err = InvalidCurrency;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match <[u8; 3]>::try_from(s.as_bytes()) {
+            Ok(code) if code.iter().all(u8::is_ascii_alphabetic) => {
+                Ok(Currency(code.map(|c| c.to_ascii_uppercase())))
+            }
+            _ => Err(InvalidCurrency(s.to_string())),
+        }
+    }
+}
+
+impl fmt::Display for Currency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl Serialize for Currency {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for Currency {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let code = String::deserialize(deserializer)?;
+        code.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case::uppercase("EUR", Ok("EUR"))]
+    #[case::lowercase("usd", Ok("USD"))]
+    #[case::too_short("EU", Err(()))]
+    #[case::too_long("EURO", Err(()))]
+    #[case::not_letters("E1R", Err(()))]
+    #[case::not_ascii("€UR", Err(()))]
+    fn test_parse_currency(#[case] code: &str, #[case] expected: Result<&str, ()>) {
+        let parsed = code.parse::<Currency>();
+        assert_eq!(
+            parsed.as_ref().map(Currency::as_str).map_err(|_| ()),
+            expected
+        );
+    }
+}
@@ -0,0 +1,2987 @@
+use crate::fx::{self, Consolidated, FxError, RateTable};
+use crate::risk::{Activity, ClientActivity};
+use crate::{
+    AccountStatus, AuditRecord, Config, Currency, DisputePolicy, DuplicatePolicy, Event,
+    ExpiredAuth, ExpiredDispute, Exposure, FeeEntry, FeeKind, FeeRefundPolicy, Input, InputType,
+    JournalEntry, LedgerAccount, Movement, NegativeBalance, Output, OverflowPolicy, RiskAction,
+    RiskHit, ShortfallPolicy, TxError,
+};
+use rust_decimal::Decimal;
+use std::collections::{BTreeSet, HashMap};
+
+/// A stateful ledger that applies transactions one at a time.
+///
+/// The engine owns the client accounts and the transaction history needed to
+/// resolve disputes, so it can be fed from any source (a csv file, a queue, a
+/// socket) and queried at any point in between.
+#[derive(Debug, Default)]
+pub struct Engine {
+    config: Config,
+    accounts: HashMap<u16, Client>,
+    txn_history: HashMap<u32, TxRecord>,
+    summary: Summary,
+    events: Vec<Event>,
+    /// The latest transaction timestamp seen, if any.
+    clock: Option<u64>,
+    /// Open disputes that can expire, by when they were opened.
+    open_disputes: BTreeSet<(u64, u32)>,
+    /// Open authorizations by auth id.
+    auths: HashMap<u32, Auth>,
+    /// Open authorizations with a timestamp, by when they were authorized.
+    timed_auths: BTreeSet<(u64, u32)>,
+    /// Open authorizations by how many transactions the engine had seen
+    /// before them.
+    queued_auths: BTreeSet<(u64, u32)>,
+    /// Deposits that haven't cleared yet, by the number of transactions the
+    /// engine had seen before them, which is unique unlike the tx id.
+    pending: HashMap<u64, PendingDeposit>,
+    /// Pending deposits by when they clear, in seconds or in transactions
+    /// seen. A deposit cleared by one is left in the other until it comes up.
+    timed_pending: BTreeSet<(u64, u64)>,
+    queued_pending: BTreeSet<(u64, u64)>,
+    /// Each client's recent transactions, kept only while there are risk
+    /// rules to check them against.
+    activity: HashMap<u16, ClientActivity>,
+    /// How many journal entries have been reported, which numbers the next.
+    journal_entries: u64,
+}
+
+/// A client's status and their balance in each currency they have used.
+#[derive(Debug, Default)]
+struct Client {
+    status: AccountStatus,
+    /// One row per currency, in the order they were first seen. Clients
+    /// rarely hold more than a handful, so a scan beats a map.
+    balances: Vec<Output>,
+}
+
+impl Client {
+    fn balance(&self, currency: Option<Currency>) -> Option<&Output> {
+        self.balances.iter().find(|acc| acc.currency == currency)
+    }
+
+    /// Returns the balance in `currency`, opening it if needed.
+    fn balance_mut(&mut self, client: u16, currency: Option<Currency>) -> &mut Output {
+        let index = match self
+            .balances
+            .iter()
+            .position(|acc| acc.currency == currency)
+        {
+            Some(index) => index,
+            None => {
+                let mut balance = Output {
+                    client,
+                    currency,
+                    ..Default::default()
+                };
+                balance.set_status(self.status);
+                self.balances.push(balance);
+                self.balances.len() - 1
+            }
+        };
+        &mut self.balances[index]
+    }
+
+    /// Applies `change` to a copy of the balance in `currency`, so it can be
+    /// checked before being [committed](Client::commit).
+    fn preview(
+        &mut self,
+        client: u16,
+        currency: Option<Currency>,
+        change: Change,
+        overflow: OverflowPolicy,
+    ) -> Result<(Output, bool), TxError> {
+        let mut balance = self.balance_mut(client, currency).clone();
+        let saturated = change.apply_to(&mut balance, overflow)?;
+        Ok((balance, saturated))
+    }
+
+    fn commit(&mut self, balance: Output) {
+        let (client, currency) = (balance.client, balance.currency);
+        *self.balance_mut(client, currency) = balance;
+    }
+
+    fn set_status(&mut self, status: AccountStatus) {
+        self.status = status;
+        for balance in &mut self.balances {
+            balance.set_status(status);
+        }
+    }
+}
+
+/// What the engine remembers about an applied deposit or withdrawal, so it
+/// can be disputed later.
+#[derive(Debug, Clone)]
+struct TxRecord {
+    client: u16,
+    kind: TxKind,
+    amount: Decimal,
+    currency: Option<Currency>,
+    state: TxState,
+    /// How many disputes were opened after the tx had been resolved.
+    redisputes: u32,
+    /// The part of `amount` under open disputes.
+    disputed: Decimal,
+    /// What the open disputes actually hold, which can be less than
+    /// `disputed` under [`ShortfallPolicy::CapHold`].
+    held: Decimal,
+    /// The part of `amount` already charged back, which can't be disputed
+    /// again.
+    charged_back: Decimal,
+    /// The part of `amount` given back by refunds, which can't be disputed
+    /// or refunded again.
+    refunded: Decimal,
+    /// The key of the deposit in [`Engine::pending`] while it hasn't cleared.
+    pending: Option<u64>,
+    /// The fee charged when the tx was applied.
+    fee: Decimal,
+    fee_refunded: bool,
+    timestamp: Option<u64>,
+    /// When the currently open dispute was opened, if it had a timestamp.
+    disputed_at: Option<u64>,
+}
+
+impl TxRecord {
+    fn new(txn: &Input, kind: TxKind, amount: Decimal, fee: Decimal) -> Self {
+        Self {
+            client: txn.client,
+            kind,
+            amount,
+            currency: txn.currency,
+            state: TxState::Settled,
+            redisputes: 0,
+            disputed: Decimal::ZERO,
+            held: Decimal::ZERO,
+            charged_back: Decimal::ZERO,
+            refunded: Decimal::ZERO,
+            pending: None,
+            fee,
+            fee_refunded: false,
+            timestamp: txn.timestamp,
+            disputed_at: None,
+        }
+    }
+
+    /// The client whose balance a dispute on the tx holds.
+    fn holder(&self) -> u16 {
+        match self.kind {
+            TxKind::Transfer { to } => to,
+            TxKind::Deposit | TxKind::Withdrawal => self.client,
+        }
+    }
+
+    /// Checks a dispute, resolve or chargeback row against the tx it names.
+    fn check_dispute_row(&self, txn: &Input) -> Result<(), TxError> {
+        if self.client != txn.client {
+            return Err(TxError::ClientMismatch);
+        }
+        if self.state == TxState::ChargedBack {
+            return Err(TxError::ChargedBack);
+        }
+        // a row without a currency refers to the tx in its own currency
+        if txn
+            .currency
+            .is_some_and(|currency| Some(currency) != self.currency)
+        {
+            return Err(TxError::CurrencyMismatch);
+        }
+        if txn.amount.is_some_and(|amount| amount <= Decimal::ZERO) {
+            return Err(TxError::InvalidAmount);
+        }
+        Ok(())
+    }
+
+    fn dispute_policy(&self, config: &Config) -> DisputePolicy {
+        match self.kind {
+            // to the receiver, a transfer is money coming in
+            TxKind::Deposit | TxKind::Transfer { .. } => config.deposit_disputes,
+            TxKind::Withdrawal => config.withdrawal_disputes,
+        }
+    }
+
+    /// The fee charged on the tx, if `r#type` is the point where
+    /// [`Config::fee_refunds`] gives it back and it hasn't been already.
+    fn refundable_fee(&self, r#type: InputType, config: &Config) -> Decimal {
+        let refund = match config.fee_refunds {
+            FeeRefundPolicy::Never => false,
+            FeeRefundPolicy::OnDispute => r#type == InputType::Dispute,
+            FeeRefundPolicy::OnChargeback => r#type == InputType::Chargeback,
+        };
+        if refund && !self.fee_refunded {
+            self.fee
+        } else {
+            Decimal::ZERO
+        }
+    }
+
+    /// A withdrawn amount is no longer in the account, so a reversal credits
+    /// it back into held instead of freezing available funds.
+    fn is_reversal(&self, config: &Config) -> bool {
+        self.kind == TxKind::Withdrawal
+            && self.dispute_policy(config) == DisputePolicy::ReversalCredit
+    }
+
+    /// What resolving or charging back `settled` of the disputed amount
+    /// does to the holder's balances.
+    fn settlement(
+        &self,
+        settled: Decimal,
+        resolve: bool,
+        config: &Config,
+    ) -> Result<Settlement, TxError> {
+        // under CapHold less than the disputed amount is held, and the held
+        // part is settled first
+        let released = settled.min(self.held);
+        let mut saturated = false;
+        let (change, losses) = match (resolve, self.kind, self.is_reversal(config)) {
+            // the held funds go back to available
+            (true, _, false) => (Change::hold(-released), Decimal::ZERO),
+            // the reversal credit is taken back
+            (true, _, true) => (Change::held(-released), -released),
+            // a charged-back deposit leaves the account
+            (false, TxKind::Deposit, _) => (Change::held(-released), -released),
+            // a charged-back transfer goes back to the payer, who isn't the
+            // holder
+            (false, TxKind::Transfer { .. }, _) => (Change::held(-released), Decimal::ZERO),
+            // a charged-back withdrawal comes back to the client, either as
+            // the reversal credit already in held...
+            (false, TxKind::Withdrawal, true) => (Change::hold(-released), Decimal::ZERO),
+            // ...or on top of the funds that were held from available
+            (false, TxKind::Withdrawal, false) => {
+                let available;
+                (available, saturated) = checked_add(released, settled, config.overflow)?;
+                let change = Change {
+                    available,
+                    held: -released,
+                    ..Default::default()
+                };
+                // `settled`, unless the sum saturated
+                (change, available - released)
+            }
+        };
+        Ok(Settlement {
+            change,
+            released,
+            losses,
+            saturated,
+        })
+    }
+
+    /// Records a [`TxRecord::settlement`] once its change has been applied.
+    fn settle(&mut self, settled: Decimal, released: Decimal, resolve: bool) {
+        self.disputed -= settled;
+        self.held -= released;
+        if !resolve {
+            self.charged_back += settled;
+        }
+        if self.disputed.is_zero() {
+            // once any part was charged back, the tx is closed for good
+            self.state = if self.charged_back.is_zero() {
+                TxState::Resolved
+            } else {
+                TxState::ChargedBack
+            };
+        }
+    }
+}
+
+/// What resolving or charging back part of a dispute does, from
+/// [`TxRecord::settlement`].
+#[derive(Debug, Clone, Copy)]
+struct Settlement {
+    change: Change,
+    /// How much held money the change moves.
+    released: Decimal,
+    /// What the change gives the holder out of chargeback losses, negative
+    /// for what it takes back.
+    losses: Decimal,
+    /// Whether the change itself saturated under
+    /// [`OverflowPolicy::Saturate`].
+    saturated: bool,
+}
+
+/// The part of a deposit still waiting to clear into available.
+#[derive(Debug, Clone)]
+struct PendingDeposit {
+    tx: u32,
+    client: u16,
+    currency: Option<Currency>,
+    amount: Decimal,
+}
+
+/// Funds held by an `authorize` row until they are captured or released.
+#[derive(Debug, Clone)]
+struct Auth {
+    client: u16,
+    currency: Option<Currency>,
+    /// What is still held, after any partial captures.
+    remaining: Decimal,
+    timestamp: Option<u64>,
+    /// How many transactions the engine had seen before the authorization.
+    seq: u64,
+}
+
+/// Where a deposit or withdrawal is in its dispute lifecycle.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TxState {
+    /// Applied and never disputed.
+    Settled,
+    /// At least part of the amount is under an open dispute.
+    Disputed,
+    /// Every dispute so far was resolved. Can be disputed again, up to
+    /// [`Config::redispute_limit`] times.
+    Resolved,
+    /// Some of the amount was charged back. Any further dispute, resolve or
+    /// chargeback is rejected.
+    ChargedBack,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum TxKind {
+    Deposit,
+    Withdrawal,
+    /// Paid by the record's client to `to`.
+    Transfer {
+        to: u16,
+    },
+}
+
+/// Signed amounts to add to an account's balances. `total` moves by their
+/// sum, so it always equals available + held + pending.
+#[derive(Debug, Default, Clone, Copy)]
+struct Change {
+    available: Decimal,
+    held: Decimal,
+    pending: Decimal,
+}
+
+impl Change {
+    fn available(amount: Decimal) -> Self {
+        Self {
+            available: amount,
+            ..Default::default()
+        }
+    }
+
+    fn held(amount: Decimal) -> Self {
+        Self {
+            held: amount,
+            ..Default::default()
+        }
+    }
+
+    /// Moves `amount` from available to held, or back when negative.
+    fn hold(amount: Decimal) -> Self {
+        Self {
+            available: -amount,
+            held: amount,
+            ..Default::default()
+        }
+    }
+
+    /// Adds `refund` to available and takes `fee` from it, capped at what is
+    /// left so the fee alone never drives available below `-overdraft`.
+    /// Returns the fee actually charged.
+    fn with_fee(
+        self,
+        account: &Output,
+        fee: Decimal,
+        refund: Decimal,
+        overdraft: Decimal,
+    ) -> (Self, Decimal) {
+        let available = self.available.saturating_add(refund);
+        let left = account
+            .available
+            .saturating_add(available)
+            .saturating_add(overdraft);
+        let fee = fee.min(left.max(Decimal::ZERO));
+        let change = Self {
+            available: available.saturating_sub(fee),
+            ..self
+        };
+        (change, fee)
+    }
+
+    /// Adds the change to `client`'s accounts in `entry`.
+    fn post(self, entry: JournalEntry, client: u16) -> JournalEntry {
+        entry
+            .client(client, LedgerAccount::Available, self.available)
+            .client(client, LedgerAccount::Held, self.held)
+            .client(client, LedgerAccount::Pending, self.pending)
+    }
+
+    /// Applies the change, leaving the account untouched if it fails.
+    /// Returns whether any balance saturated under [`OverflowPolicy::Saturate`].
+    fn apply_to(self, account: &mut Output, policy: OverflowPolicy) -> Result<bool, TxError> {
+        let mut saturated = false;
+        let mut add = |balance: Decimal, delta: Decimal| {
+            let (sum, clamped) = checked_add(balance, delta, policy)?;
+            saturated |= clamped;
+            Ok(sum)
+        };
+        let available = add(account.available, self.available)?;
+        let held = add(account.held, self.held)?;
+        let pending = add(account.pending, self.pending)?;
+        let total = add(account.total, self.available)
+            .and_then(|total| add(total, self.held))
+            .and_then(|total| add(total, self.pending))?;
+        account.available = available;
+        account.held = held;
+        account.pending = pending;
+        account.total = total;
+        Ok(saturated)
+    }
+}
+
+/// What a successfully applied transaction did. Variants that move money
+/// carry the amount that moved between balances.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Applied {
+    Deposit(Decimal),
+    Withdrawal(Decimal),
+    Transfer(Decimal),
+    /// The amount moved into held.
+    Authorize(Decimal),
+    /// The amount taken from held.
+    Capture(Decimal),
+    /// The amount released from held.
+    Void(Decimal),
+    /// The amount credited to available.
+    Refund(Decimal),
+    /// The amount moved into held, which can be less than the disputed
+    /// amount under [`ShortfallPolicy::CapHold`].
+    Dispute(Decimal),
+    /// The amount released from held.
+    Resolve(Decimal),
+    /// The amount removed from held. A charged-back withdrawal also credits
+    /// the client with the disputed amount.
+    Chargeback(Decimal),
+    Unlock,
+    Freeze,
+    Close,
+}
+
+/// Running counts of what the engine has done so far.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Summary {
+    pub applied: u64,
+    pub rejected: u64,
+    /// Deposits, withdrawals, transfers and authorizations that reused a known
+    /// tx id, whether or not the [`DuplicatePolicy`] let them through.
+    pub duplicates: u64,
+}
+
+impl Engine {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_config(config: Config) -> Self {
+        Self {
+            config,
+            ..Default::default()
+        }
+    }
+
+    /// Applies a single transaction, returning what it did or why it was
+    /// ignored.
+    ///
+    /// A rejected transaction leaves balances and the transaction history
+    /// untouched.
+    pub fn apply(&mut self, txn: Input) -> Result<Applied, TxError> {
+        if let Some(now) = txn.timestamp {
+            self.advance_clock(now);
+        }
+        let seen = self.seen();
+        self.sweep(
+            |engine| &mut engine.queued_pending,
+            |due| due < seen,
+            |engine, _, id| engine.clear_deposit(id),
+        );
+        if let Some(limit) = self.config.auth_expiry_txns {
+            self.sweep(
+                |engine| &mut engine.queued_auths,
+                |seq| seq.saturating_add(limit) < seen,
+                |engine, _, tx| engine.expire_auth(tx),
+            );
+        }
+        let client = txn.client;
+        let activity = (!self.config.risk.is_empty()).then(|| Activity::new(&txn, seen));
+        let result = self.try_apply(txn, activity.as_ref());
+        match result {
+            Ok(_) => self.summary.applied += 1,
+            Err(_) => self.summary.rejected += 1,
+        }
+        if result.is_ok()
+            && let Some(activity) = activity
+        {
+            self.activity
+                .entry(client)
+                .or_default()
+                .record(&self.config.risk, activity);
+        }
+        result
+    }
+
+    /// Checks `txn` against the risk rules, reporting every rule it hits and
+    /// flagging the account for those that only flag.
+    fn screen(&mut self, txn: &Input, next: &Activity) -> Result<(), TxError> {
+        let empty = ClientActivity::default();
+        let history = self.activity.get(&txn.client).unwrap_or(&empty);
+        let default = Output::default();
+        let account = self
+            .accounts
+            .get(&txn.client)
+            .and_then(|client| client.balance(txn.currency))
+            .unwrap_or(&default);
+        let scripted = self
+            .config
+            .risk
+            .scripts()
+            .iter()
+            .filter(|rule| rule.matches(txn, account));
+        let hits: Vec<_> = history
+            .hits(&self.config.risk, next)
+            .map(|rule| (rule.id.clone(), rule.action))
+            .chain(scripted.map(|rule| (rule.id.clone(), rule.action)))
+            .collect();
+        let mut rejected = false;
+        for (rule, action) in hits {
+            match action {
+                RiskAction::Reject => rejected = true,
+                RiskAction::Flag => {
+                    let client = self.accounts.entry(txn.client).or_default();
+                    client.balance_mut(txn.client, txn.currency).flagged = true;
+                }
+            }
+            self.events.push(Event::RiskHit(RiskHit {
+                tx: txn.tx,
+                client: txn.client,
+                rule,
+                action,
+            }));
+        }
+        if rejected {
+            return Err(TxError::RiskRejected);
+        }
+        Ok(())
+    }
+
+    /// Applies `txn`, screening it against the risk rules once the account
+    /// is known to accept it, so locked accounts don't rack up rule hits.
+    fn try_apply(&mut self, txn: Input, activity: Option<&Activity>) -> Result<Applied, TxError> {
+        let client = self.accounts.entry(txn.client).or_default();
+        // every client that shows up gets at least one row in the output
+        if client.balances.is_empty() {
+            client.balance_mut(txn.client, txn.currency);
+        }
+
+        if !txn.r#type.is_admin() {
+            match client.status {
+                AccountStatus::Active => {}
+                AccountStatus::Closed => return Err(TxError::AccountClosed),
+                AccountStatus::Locked | AccountStatus::Frozen => {
+                    return Err(TxError::AccountLocked);
+                }
+            }
+        }
+        if let Some(activity) = activity {
+            self.screen(&txn, activity)?;
+        }
+
+        let (tx, client) = (txn.tx, txn.client);
+        let (applied, saturated) = match txn.r#type {
+            InputType::Unlock | InputType::Freeze | InputType::Close => {
+                (self.apply_admin(txn)?, false)
+            }
+            InputType::Deposit | InputType::Withdrawal => self.apply_deposit_or_withdrawal(&txn)?,
+            InputType::Transfer => self.apply_transfer(&txn)?,
+            InputType::Authorize => self.apply_authorize(&txn)?,
+            InputType::Capture | InputType::Void => self.apply_capture_or_void(&txn)?,
+            InputType::Refund => self.apply_refund(&txn)?,
+            InputType::Dispute => self.apply_dispute(&txn)?,
+            InputType::Resolve | InputType::Chargeback => self.apply_settlement(&txn)?,
+        };
+        if saturated {
+            self.events.push(Event::Saturated { tx, client });
+        }
+        Ok(applied)
+    }
+
+    /// Unlocks, freezes or closes an account, recording who did it.
+    fn apply_admin(&mut self, txn: Input) -> Result<Applied, TxError> {
+        let actor = txn.actor.ok_or(TxError::MissingActor)?;
+        let client = self
+            .accounts
+            .get_mut(&txn.client)
+            .expect("opened by try_apply");
+        let before = client.status;
+        let (after, applied) = match (txn.r#type, before) {
+            (_, AccountStatus::Closed) => return Err(TxError::AccountClosed),
+            (InputType::Unlock, AccountStatus::Active) => return Err(TxError::NotLocked),
+            (InputType::Unlock, _) => (AccountStatus::Active, Applied::Unlock),
+            (InputType::Freeze, AccountStatus::Active) => (AccountStatus::Frozen, Applied::Freeze),
+            (InputType::Freeze, _) => return Err(TxError::AccountLocked),
+            _ => (AccountStatus::Closed, Applied::Close),
+        };
+        client.set_status(after);
+        self.events.push(Event::Audit(AuditRecord {
+            tx: txn.tx,
+            client: txn.client,
+            action: txn.r#type,
+            actor,
+            reason: txn.reason,
+            before,
+            after,
+        }));
+        Ok(applied)
+    }
+
+    /// Applies a deposit, into pending if the funds availability policy
+    /// holds it, or a withdrawal. Returns what it did and whether a balance
+    /// saturated, like the other `apply_` methods.
+    fn apply_deposit_or_withdrawal(&mut self, txn: &Input) -> Result<(Applied, bool), TxError> {
+        let amount = txn.amount.ok_or(TxError::MissingAmount)?;
+        if amount <= Decimal::ZERO {
+            return Err(TxError::InvalidAmount);
+        }
+        let duplicate = self.check_duplicate(txn.tx)?;
+        let is_deposit = txn.r#type == InputType::Deposit;
+        let kind = if is_deposit {
+            TxKind::Deposit
+        } else {
+            TxKind::Withdrawal
+        };
+        let client = self
+            .accounts
+            .get_mut(&txn.client)
+            .expect("opened by try_apply");
+        let account = client.balance_mut(txn.client, txn.currency);
+        let fee = self.config.fees.fee(txn.r#type, amount);
+        let overdraft = self.config.overdrafts.limit(txn.client, txn.currency);
+        // a withdrawal has to cover its fee, other fees are capped at what
+        // the client has, overdraft included
+        if !is_deposit && !covers(account, overdraft, amount, fee) {
+            return Err(TxError::InsufficientFunds);
+        }
+        // a deposit held only for a time can't clear without a timestamp
+        let hold = is_deposit
+            .then(|| self.config.funds_availability.hold(amount))
+            .flatten()
+            .filter(|rule| rule.transactions.is_some() || txn.timestamp.is_some())
+            .map(|rule| (rule.transactions, rule.seconds));
+        let change = match hold {
+            Some(_) => Change {
+                pending: amount,
+                ..Default::default()
+            },
+            None if is_deposit => Change::available(amount),
+            None => Change::available(-amount),
+        };
+        let (change, fee) = change.with_fee(account, fee, Decimal::ZERO, overdraft);
+        let saturated = change.apply_to(account, self.config.overflow)?;
+        self.book_fee(txn, txn.client, txn.currency, FeeKind::Charge, fee);
+        let paid_in = if is_deposit { amount } else { -amount };
+        self.journal(txn.tx, Movement::Tx(txn.r#type), txn.currency, |entry| {
+            change
+                .post(entry, txn.client)
+                .external(LedgerAccount::Fees, -fee)
+                .external(LedgerAccount::Settlement, paid_in)
+        });
+        let mut record = TxRecord::new(txn, kind, amount, fee);
+        if let Some((transactions, seconds)) = hold {
+            let id = self.seen();
+            if let Some(transactions) = transactions {
+                self.queued_pending
+                    .insert((id.saturating_add(transactions), id));
+            }
+            if let (Some(seconds), Some(at)) = (seconds, txn.timestamp) {
+                self.timed_pending.insert((at.saturating_add(seconds), id));
+            }
+            self.pending.insert(
+                id,
+                PendingDeposit {
+                    tx: txn.tx,
+                    client: txn.client,
+                    currency: txn.currency,
+                    amount,
+                },
+            );
+            record.pending = Some(id);
+        }
+        self.record_tx(txn.tx, record, duplicate);
+        let applied = if is_deposit {
+            Applied::Deposit(amount)
+        } else {
+            Applied::Withdrawal(amount)
+        };
+        Ok((applied, saturated))
+    }
+
+    /// Moves funds from the client to the transfer's destination.
+    fn apply_transfer(&mut self, txn: &Input) -> Result<(Applied, bool), TxError> {
+        let amount = txn.amount.ok_or(TxError::MissingAmount)?;
+        let to = txn.destination.ok_or(TxError::MissingDestination)?;
+        if to == txn.client {
+            return Err(TxError::InvalidDestination);
+        }
+        if amount <= Decimal::ZERO {
+            return Err(TxError::InvalidAmount);
+        }
+        let duplicate = self.check_duplicate(txn.tx)?;
+        if self.accounts.entry(to).or_default().status != AccountStatus::Active {
+            return Err(TxError::DestinationLocked);
+        }
+        let [Some(source), Some(destination)] = self.accounts.get_disjoint_mut([&txn.client, &to])
+        else {
+            unreachable!("both clients were opened above");
+        };
+        let fee = self.config.fees.fee(txn.r#type, amount);
+        let overdraft = self.config.overdrafts.limit(txn.client, txn.currency);
+        let account = source.balance_mut(txn.client, txn.currency);
+        if !covers(account, overdraft, amount, fee) {
+            return Err(TxError::InsufficientFunds);
+        }
+        let (change, fee) =
+            Change::available(-amount).with_fee(account, fee, Decimal::ZERO, overdraft);
+        // both legs are tried on copies first, so neither lands if the other
+        // would overflow
+        let overflow = self.config.overflow;
+        let (debited, debit_saturated) =
+            source.preview(txn.client, txn.currency, change, overflow)?;
+        let (credited, credit_saturated) =
+            destination.preview(to, txn.currency, Change::available(amount), overflow)?;
+        source.commit(debited);
+        destination.commit(credited);
+        self.book_fee(txn, txn.client, txn.currency, FeeKind::Charge, fee);
+        self.journal(txn.tx, Movement::Tx(txn.r#type), txn.currency, |entry| {
+            let entry = change.post(entry, txn.client);
+            Change::available(amount)
+                .post(entry, to)
+                .external(LedgerAccount::Fees, -fee)
+        });
+        let record = TxRecord::new(txn, TxKind::Transfer { to }, amount, fee);
+        self.record_tx(txn.tx, record, duplicate);
+        Ok((
+            Applied::Transfer(amount),
+            debit_saturated || credit_saturated,
+        ))
+    }
+
+    /// Holds funds under the row's tx id until they are captured or released.
+    fn apply_authorize(&mut self, txn: &Input) -> Result<(Applied, bool), TxError> {
+        let amount = txn.amount.ok_or(TxError::MissingAmount)?;
+        if amount <= Decimal::ZERO {
+            return Err(TxError::InvalidAmount);
+        }
+        if self.auths.contains_key(&txn.tx) || self.txn_history.contains_key(&txn.tx) {
+            self.summary.duplicates += 1;
+            return Err(TxError::DuplicateTx);
+        }
+        let client = self
+            .accounts
+            .get_mut(&txn.client)
+            .expect("opened by try_apply");
+        let account = client.balance_mut(txn.client, txn.currency);
+        let overdraft = self.config.overdrafts.limit(txn.client, txn.currency);
+        if !covers(account, overdraft, amount, Decimal::ZERO) {
+            return Err(TxError::InsufficientFunds);
+        }
+        let change = Change::hold(amount);
+        let saturated = change.apply_to(account, self.config.overflow)?;
+        self.journal(txn.tx, Movement::Tx(txn.r#type), txn.currency, |entry| {
+            change.post(entry, txn.client)
+        });
+        let seq = self.seen();
+        self.auths.insert(
+            txn.tx,
+            Auth {
+                client: txn.client,
+                currency: txn.currency,
+                remaining: amount,
+                timestamp: txn.timestamp,
+                seq,
+            },
+        );
+        if let Some(at) = txn.timestamp {
+            self.timed_auths.insert((at, txn.tx));
+        }
+        self.queued_auths.insert((seq, txn.tx));
+        Ok((Applied::Authorize(amount), saturated))
+    }
+
+    /// Settles all or part of an open authorization, or releases what is
+    /// left of it.
+    fn apply_capture_or_void(&mut self, txn: &Input) -> Result<(Applied, bool), TxError> {
+        let auth = self.auths.get_mut(&txn.tx).ok_or(TxError::UnknownAuth)?;
+        if auth.client != txn.client {
+            return Err(TxError::ClientMismatch);
+        }
+        if txn
+            .currency
+            .is_some_and(|currency| Some(currency) != auth.currency)
+        {
+            return Err(TxError::CurrencyMismatch);
+        }
+        let currency = auth.currency;
+        let client = self
+            .accounts
+            .get_mut(&txn.client)
+            .expect("opened by try_apply");
+        let account = client.balance_mut(txn.client, currency);
+        let capture = txn.r#type == InputType::Capture;
+        let (change, fee, taken) = if capture {
+            let amount = txn.amount.unwrap_or(auth.remaining);
+            if amount <= Decimal::ZERO {
+                return Err(TxError::InvalidAmount);
+            }
+            if amount > auth.remaining {
+                return Err(TxError::ExceedsAuthorized);
+            }
+            let overdraft = self.config.overdrafts.limit(txn.client, currency);
+            let fee = self.config.fees.fee(txn.r#type, amount);
+            let (change, fee) =
+                Change::held(-amount).with_fee(account, fee, Decimal::ZERO, overdraft);
+            (change, fee, amount)
+        } else {
+            (Change::hold(-auth.remaining), Decimal::ZERO, auth.remaining)
+        };
+        let saturated = change.apply_to(account, self.config.overflow)?;
+        auth.remaining -= taken;
+        let (remaining, timestamp, seq) = (auth.remaining, auth.timestamp, auth.seq);
+        self.book_fee(txn, txn.client, currency, FeeKind::Charge, fee);
+        // a void only releases the hold, nothing leaves
+        let paid_out = if capture { taken } else { Decimal::ZERO };
+        self.journal(txn.tx, Movement::Tx(txn.r#type), currency, |entry| {
+            change
+                .post(entry, txn.client)
+                .external(LedgerAccount::Fees, -fee)
+                .external(LedgerAccount::Settlement, -paid_out)
+        });
+        if remaining.is_zero() {
+            self.close_auth(txn.tx, timestamp, seq);
+        }
+        if !capture {
+            return Ok((Applied::Void(taken), saturated));
+        }
+        // what was captured can be disputed like a withdrawal, with every
+        // partial capture adding to it
+        match self.txn_history.get_mut(&txn.tx) {
+            Some(record) => {
+                record.amount += taken;
+                record.fee += fee;
+            }
+            None => {
+                let record = TxRecord {
+                    currency,
+                    ..TxRecord::new(txn, TxKind::Withdrawal, taken, fee)
+                };
+                self.txn_history.insert(txn.tx, record);
+            }
+        }
+        Ok((Applied::Capture(taken), saturated))
+    }
+
+    /// Gives back all or part of a withdrawal.
+    fn apply_refund(&mut self, txn: &Input) -> Result<(Applied, bool), TxError> {
+        let record = self
+            .txn_history
+            .get_mut(&txn.tx)
+            .ok_or(TxError::UnknownTx)?;
+        if record.client != txn.client {
+            return Err(TxError::ClientMismatch);
+        }
+        if record.kind != TxKind::Withdrawal {
+            return Err(TxError::NotRefundable);
+        }
+        if record.state == TxState::ChargedBack {
+            return Err(TxError::ChargedBack);
+        }
+        if txn
+            .currency
+            .is_some_and(|currency| Some(currency) != record.currency)
+        {
+            return Err(TxError::CurrencyMismatch);
+        }
+        // the part under dispute may still come back through a chargeback,
+        // so it can't be refunded as well
+        let refundable = record.amount - record.refunded - record.disputed;
+        let amount = txn.amount.unwrap_or(refundable);
+        if amount <= Decimal::ZERO && txn.amount.is_some() {
+            return Err(TxError::InvalidAmount);
+        }
+        if amount.is_zero() || amount > refundable {
+            return Err(TxError::ExceedsRefundable);
+        }
+        let currency = record.currency;
+        let client = self
+            .accounts
+            .get_mut(&txn.client)
+            .expect("opened by try_apply");
+        let account = client.balance_mut(txn.client, currency);
+        let overdraft = self.config.overdrafts.limit(txn.client, currency);
+        let fee = self.config.fees.fee(txn.r#type, amount);
+        let (change, fee) =
+            Change::available(amount).with_fee(account, fee, Decimal::ZERO, overdraft);
+        let saturated = change.apply_to(account, self.config.overflow)?;
+        record.refunded += amount;
+        self.book_fee(txn, txn.client, currency, FeeKind::Charge, fee);
+        self.journal(txn.tx, Movement::Tx(txn.r#type), currency, |entry| {
+            change
+                .post(entry, txn.client)
+                .external(LedgerAccount::Fees, -fee)
+                .external(LedgerAccount::Settlement, amount)
+        });
+        Ok((Applied::Refund(amount), saturated))
+    }
+
+    /// Opens a dispute on all or part of a tx, holding the disputed funds or
+    /// crediting a reversal, depending on the [`DisputePolicy`].
+    fn apply_dispute(&mut self, txn: &Input) -> Result<(Applied, bool), TxError> {
+        let record = self
+            .txn_history
+            .get_mut(&txn.tx)
+            .ok_or(TxError::UnknownTx)?;
+        record.check_dispute_row(txn)?;
+        let holder = record.holder();
+        let currency = record.currency;
+        let (client, payer) = holder_and_payer(&mut self.accounts, holder, txn.client)?;
+        let account = client.balance_mut(holder, currency);
+        let overdraft = self.config.overdrafts.limit(holder, currency);
+        let reversal = record.is_reversal(&self.config);
+        if record.dispute_policy(&self.config) == DisputePolicy::Disallow {
+            return Err(TxError::DisputeNotAllowed);
+        }
+        if let (Some(window), Some(at), Some(now)) =
+            (self.config.dispute_window, record.timestamp, txn.timestamp)
+            && now.saturating_sub(at) > window
+        {
+            return Err(TxError::DisputeWindowClosed);
+        }
+        let redispute = record.state == TxState::Resolved;
+        if redispute
+            && self
+                .config
+                .redispute_limit
+                .is_some_and(|limit| record.redisputes >= limit)
+        {
+            return Err(TxError::RedisputeLimit);
+        }
+        // without an amount, the whole undisputed remainder is disputed
+        let disputable = record.amount - record.charged_back - record.disputed - record.refunded;
+        let disputed = match txn.amount {
+            None if record.state == TxState::Disputed => {
+                return Err(TxError::AlreadyDisputed);
+            }
+            None => disputable,
+            Some(amount) => amount,
+        };
+        if disputed.is_zero() || disputed > disputable {
+            return Err(TxError::ExceedsOriginal);
+        }
+        // a deposit that hasn't cleared is held out of its pending part first
+        let pending = record.pending.and_then(|id| self.pending.get_mut(&id));
+        let from_pending = match &pending {
+            Some(deposit) if !reversal => deposit.amount.min(disputed),
+            _ => Decimal::ZERO,
+        };
+        let from_available = disputed - from_pending;
+        let mut held = disputed;
+        let mut exposure = None;
+        if !reversal && account.available < from_available {
+            let shortfall = from_available - account.available.max(Decimal::ZERO);
+            match self.config.shortfall {
+                ShortfallPolicy::Reject => return Err(TxError::DisputeExceedsAvailable),
+                ShortfallPolicy::AllowNegative => {}
+                ShortfallPolicy::CapHold => held -= shortfall,
+            }
+            exposure = Some(Exposure {
+                tx: txn.tx,
+                client: holder,
+                currency,
+                disputed,
+                shortfall,
+                policy: self.config.shortfall,
+            });
+        }
+        // a reversal credits the withdrawal back while it is disputed
+        let reversal_credit = if reversal { held } else { Decimal::ZERO };
+        let change = if reversal {
+            Change::held(held)
+        } else {
+            // negating a zero would leave -0 in the output
+            let pending = if from_pending.is_zero() {
+                Decimal::ZERO
+            } else {
+                -from_pending
+            };
+            Change {
+                available: from_pending - held,
+                held,
+                pending,
+            }
+        };
+        let refund = record.refundable_fee(txn.r#type, &self.config);
+        let fee = self.config.fees.fee(txn.r#type, disputed);
+        let (saturated, fee_charged) = apply_dispute_change(
+            account,
+            payer,
+            change,
+            fee,
+            refund,
+            overdraft,
+            self.config.overflow,
+        )?;
+        record.fee_refunded |= refund > Decimal::ZERO;
+        if let Some(deposit) = pending {
+            deposit.amount -= from_pending;
+        }
+        if let Some(id) = record.pending
+            && self.pending.get(&id).is_some_and(|d| d.amount.is_zero())
+        {
+            self.pending.remove(&id);
+            record.pending = None;
+        }
+        if let Some(exposure) = &exposure {
+            client.balance_mut(holder, currency).flagged |=
+                exposure.policy == ShortfallPolicy::AllowNegative;
+        }
+        if record.state != TxState::Disputed {
+            record.state = TxState::Disputed;
+            record.redisputes += u32::from(redispute);
+            record.disputed_at = txn.timestamp;
+            if let Some(at) = txn.timestamp {
+                self.open_disputes.insert((at, txn.tx));
+            }
+        }
+        record.disputed += disputed;
+        record.held += held;
+        self.book_fee(txn, txn.client, currency, FeeKind::Refund, refund);
+        self.book_fee(txn, holder, currency, FeeKind::Charge, fee_charged);
+        if let Some(exposure) = exposure {
+            self.events.push(Event::Exposure(exposure));
+        }
+        self.journal(txn.tx, Movement::Tx(txn.r#type), currency, |entry| {
+            dispute_entry(
+                entry,
+                txn.client,
+                holder,
+                change,
+                refund,
+                refund,
+                fee_charged,
+            )
+            .external(LedgerAccount::ChargebackLosses, reversal_credit)
+        });
+        Ok((Applied::Dispute(held), saturated))
+    }
+
+    /// Resolves or charges back all or part of an open dispute. Once a tx
+    /// with a charged-back part has nothing left under dispute, the account
+    /// of the client who disputed it is locked, which for a transfer is the
+    /// sender rather than the receiver holding the funds.
+    fn apply_settlement(&mut self, txn: &Input) -> Result<(Applied, bool), TxError> {
+        let record = self
+            .txn_history
+            .get_mut(&txn.tx)
+            .ok_or(TxError::UnknownTx)?;
+        record.check_dispute_row(txn)?;
+        let holder = record.holder();
+        let currency = record.currency;
+        let (client, payer) = holder_and_payer(&mut self.accounts, holder, txn.client)?;
+        let account = client.balance_mut(holder, currency);
+        let overdraft = self.config.overdrafts.limit(holder, currency);
+        if record.state != TxState::Disputed {
+            return Err(TxError::NotDisputed);
+        }
+        let settled = txn.amount.unwrap_or(record.disputed);
+        if settled > record.disputed {
+            return Err(TxError::ExceedsDisputed);
+        }
+        let resolve = txn.r#type == InputType::Resolve;
+        let Settlement {
+            change,
+            released,
+            losses,
+            saturated: clamped,
+        } = record.settlement(settled, resolve, &self.config)?;
+        let refund = record.refundable_fee(txn.r#type, &self.config);
+        // a charged-back transfer returns what the hold recovered
+        let credit = match payer {
+            Some(_) if !resolve => refund + released,
+            _ => refund,
+        };
+        let fee = self.config.fees.fee(txn.r#type, settled);
+        let (saturated, fee_charged) = apply_dispute_change(
+            account,
+            payer,
+            change,
+            fee,
+            credit,
+            overdraft,
+            self.config.overflow,
+        )?;
+        record.settle(settled, released, resolve);
+        record.fee_refunded |= refund > Decimal::ZERO;
+        if record.state != TxState::Disputed
+            && let Some(at) = record.disputed_at.take()
+        {
+            self.open_disputes.remove(&(at, txn.tx));
+        }
+        // the account is only locked once nothing is left under dispute, so
+        // the rest of a partial chargeback can still be settled
+        if record.state == TxState::ChargedBack {
+            lock(&mut self.accounts, txn.client);
+        }
+        self.book_fee(txn, txn.client, currency, FeeKind::Refund, refund);
+        self.book_fee(txn, holder, currency, FeeKind::Charge, fee_charged);
+        self.journal(txn.tx, Movement::Tx(txn.r#type), currency, |entry| {
+            dispute_entry(
+                entry,
+                txn.client,
+                holder,
+                change,
+                credit,
+                refund,
+                fee_charged,
+            )
+            .external(LedgerAccount::ChargebackLosses, losses)
+        });
+        let applied = if resolve {
+            Applied::Resolve(released)
+        } else {
+            Applied::Chargeback(released)
+        };
+        Ok((applied, saturated || clamped))
+    }
+
+    /// Checks the tx id of a new deposit, withdrawal or transfer against the
+    /// history under the [`DuplicatePolicy`], returning whether it reuses a
+    /// known id.
+    fn check_duplicate(&mut self, tx: u32) -> Result<bool, TxError> {
+        // an open authorization keeps its id until it is captured or released
+        if self.auths.contains_key(&tx) {
+            self.summary.duplicates += 1;
+            return Err(TxError::DuplicateTx);
+        }
+        let Some(record) = self.txn_history.get(&tx) else {
+            return Ok(false);
+        };
+        // replacing a tx that is under dispute would orphan its held funds,
+        // and one that was charged back would reopen it
+        let accepted = match self.config.duplicates {
+            DuplicatePolicy::FirstWins => true,
+            DuplicatePolicy::LastWins => {
+                matches!(record.state, TxState::Settled | TxState::Resolved)
+            }
+            DuplicatePolicy::Reject => false,
+        };
+        if !accepted {
+            self.summary.duplicates += 1;
+            return Err(TxError::DuplicateTx);
+        }
+        Ok(true)
+    }
+
+    /// Remembers an applied deposit, withdrawal or transfer, so it can be
+    /// disputed later.
+    fn record_tx(&mut self, tx: u32, record: TxRecord, duplicate: bool) {
+        if duplicate {
+            self.summary.duplicates += 1;
+            if self.config.duplicates != DuplicatePolicy::LastWins {
+                return;
+            }
+        }
+        self.txn_history.insert(tx, record);
+    }
+
+    /// How many transactions the engine has been given so far.
+    fn seen(&self) -> u64 {
+        self.summary.applied + self.summary.rejected
+    }
+
+    /// Hands every item of `set` whose key is `due` to `expire`, oldest
+    /// first. Items that can't expire yet go back into the set, so the next
+    /// sweep tries them again.
+    fn sweep<T: Copy + Ord>(
+        &mut self,
+        set: fn(&mut Self) -> &mut BTreeSet<(u64, T)>,
+        due: impl Fn(u64) -> bool,
+        expire: fn(&mut Self, u64, T) -> Result<(), TxError>,
+    ) {
+        let mut deferred = Vec::new();
+        while let Some(&(key, item)) = set(self).first()
+            && due(key)
+        {
+            set(self).pop_first();
+            if expire(self, key, item).is_err() {
+                deferred.push((key, item));
+            }
+        }
+        set(self).extend(deferred);
+    }
+
+    /// Moves a pending deposit into available, if it hasn't cleared already.
+    /// If that would overflow under a rejecting policy, the deposit stays
+    /// pending and is reported as [`Event::Deferred`].
+    fn clear_deposit(&mut self, id: u64) -> Result<(), TxError> {
+        let Some(deposit) = self.pending.get(&id) else {
+            return Ok(());
+        };
+        let Some(holder) = self.accounts.get_mut(&deposit.client) else {
+            return Ok(());
+        };
+        let account = holder.balance_mut(deposit.client, deposit.currency);
+        let change = Change {
+            available: deposit.amount,
+            pending: -deposit.amount,
+            ..Default::default()
+        };
+        let (tx, client) = (deposit.tx, deposit.client);
+        let saturated = change
+            .apply_to(account, self.config.overflow)
+            .inspect_err(|_| self.events.push(Event::Deferred { tx, client }))?;
+        let currency = deposit.currency;
+        self.pending.remove(&id);
+        self.journal(tx, Movement::DepositCleared, currency, |entry| {
+            change.post(entry, client)
+        });
+        if saturated {
+            self.events.push(Event::Saturated { tx, client });
+        }
+        Ok(())
+    }
+
+    /// Forgets an authorization that has nothing left to hold.
+    fn close_auth(&mut self, tx: u32, timestamp: Option<u64>, seq: u64) {
+        self.auths.remove(&tx);
+        if let Some(at) = timestamp {
+            self.timed_auths.remove(&(at, tx));
+        }
+        self.queued_auths.remove(&(seq, tx));
+    }
+
+    /// Voids an authorization that ran out of time, releasing what is left
+    /// of it. If that would overflow under a rejecting policy, the
+    /// authorization stays open and is reported as [`Event::Deferred`].
+    fn expire_auth(&mut self, tx: u32) -> Result<(), TxError> {
+        let Some(auth) = self.auths.get(&tx) else {
+            return Ok(());
+        };
+        let (client, currency, released) = (auth.client, auth.currency, auth.remaining);
+        let (timestamp, seq) = (auth.timestamp, auth.seq);
+        let Some(holder) = self.accounts.get_mut(&client) else {
+            return Ok(());
+        };
+        let account = holder.balance_mut(client, currency);
+        let change = Change::hold(-released);
+        let saturated = change
+            .apply_to(account, self.config.overflow)
+            .inspect_err(|_| self.events.push(Event::Deferred { tx, client }))?;
+        self.close_auth(tx, timestamp, seq);
+        self.journal(tx, Movement::AuthExpired, currency, |entry| {
+            change.post(entry, client)
+        });
+        self.events.push(Event::AuthExpired(ExpiredAuth {
+            tx,
+            client,
+            currency,
+            released,
+            authorized_at: timestamp,
+        }));
+        if saturated {
+            self.events.push(Event::Saturated { tx, client });
+        }
+        Ok(())
+    }
+
+    /// Resolves a dispute opened at `opened_at` that ran out of time. If
+    /// releasing the held funds would overflow under a rejecting policy, the
+    /// dispute stays open and is reported as [`Event::Deferred`].
+    fn expire_dispute(&mut self, opened_at: u64, tx: u32) -> Result<(), TxError> {
+        let Some(record) = self.txn_history.get_mut(&tx) else {
+            return Ok(());
+        };
+        // the hold on a disputed transfer sits with whoever received it
+        let (client, currency) = (record.holder(), record.currency);
+        let Some(holder) = self.accounts.get_mut(&client) else {
+            return Ok(());
+        };
+        let account = holder.balance_mut(client, currency);
+        let disputed = record.disputed;
+        // a resolve only moves held funds, so it can't overflow on its own
+        let Settlement {
+            change,
+            released,
+            losses,
+            ..
+        } = record.settlement(disputed, true, &self.config)?;
+        let saturated = change
+            .apply_to(account, self.config.overflow)
+            .inspect_err(|_| self.events.push(Event::Deferred { tx, client }))?;
+        record.settle(disputed, released, true);
+        record.disputed_at = None;
+        // expiring the rest of a partial chargeback closes it
+        if record.state == TxState::ChargedBack {
+            lock(&mut self.accounts, record.client);
+        }
+        let timeout = self.config.dispute_timeout.unwrap_or_default();
+        self.events.push(Event::DisputeExpired(ExpiredDispute {
+            tx,
+            client,
+            currency,
+            disputed,
+            released,
+            opened_at,
+            expired_at: opened_at.saturating_add(timeout),
+        }));
+        self.journal(tx, Movement::DisputeExpired, currency, |entry| {
+            change
+                .post(entry, client)
+                .external(LedgerAccount::ChargebackLosses, losses)
+        });
+        if saturated {
+            self.events.push(Event::Saturated { tx, client });
+        }
+        Ok(())
+    }
+
+    /// Moves the engine's clock forward to `now` (in the same unit as
+    /// [`Input::timestamp`]), resolving every dispute that has been open for
+    /// longer than [`Config::dispute_timeout`] and voiding every authorization
+    /// older than [`Config::auth_expiry`]. [`Engine::apply`] does this for
+    /// every timestamped transaction; call it directly to expire disputes and
+    /// authorizations when no traffic is coming in. The clock never moves
+    /// backwards.
+    pub fn advance_clock(&mut self, now: u64) {
+        if self.clock.is_some_and(|clock| clock >= now) {
+            return;
+        }
+        self.clock = Some(now);
+        self.sweep(
+            |engine| &mut engine.timed_pending,
+            |due| due <= now,
+            |engine, _, id| engine.clear_deposit(id),
+        );
+        if let Some(expiry) = self.config.auth_expiry {
+            self.sweep(
+                |engine| &mut engine.timed_auths,
+                |at| at.saturating_add(expiry) <= now,
+                |engine, _, tx| engine.expire_auth(tx),
+            );
+        }
+        if let Some(timeout) = self.config.dispute_timeout {
+            self.sweep(
+                |engine| &mut engine.open_disputes,
+                |opened_at| opened_at.saturating_add(timeout) <= now,
+                Self::expire_dispute,
+            );
+        }
+    }
+
+    /// The latest transaction timestamp seen, or passed to
+    /// [`Engine::advance_clock`].
+    pub fn clock(&self) -> Option<u64> {
+        self.clock
+    }
+
+    /// Takes every [`Event`] recorded since the last call, oldest first.
+    pub fn drain_events(&mut self) -> impl Iterator<Item = Event> + '_ {
+        self.events.drain(..)
+    }
+
+    /// Returns the current state of a client's account for transactions
+    /// without a currency, if there have been any.
+    pub fn account(&self, client: u16) -> Option<&Output> {
+        self.account_in(client, None)
+    }
+
+    /// Returns the current state of a client's account in `currency`, if the
+    /// client has used it.
+    pub fn account_in(&self, client: u16, currency: Option<Currency>) -> Option<&Output> {
+        self.accounts.get(&client)?.balance(currency)
+    }
+
+    /// Iterates over every known account, one per client and currency, in no
+    /// particular order.
+    pub fn accounts(&self) -> impl Iterator<Item = &Output> {
+        self.accounts.values().flat_map(|client| &client.balances)
+    }
+
+    /// Returns where a deposit or withdrawal is in its dispute lifecycle, if
+    /// the tx id is known.
+    pub fn tx_state(&self, tx: u32) -> Option<TxState> {
+        self.txn_history.get(&tx).map(|record| record.state)
+    }
+
+    /// Reports every client's total in `base`, converted with the rates in
+    /// effect at the engine's [clock](Engine::clock). Balances without a
+    /// currency are taken to be in `base` already.
+    pub fn consolidated(
+        &self,
+        rates: &RateTable,
+        base: Currency,
+    ) -> Result<Vec<Consolidated>, FxError> {
+        fx::consolidate(self.accounts(), rates, base, self.clock)
+    }
+
+    /// Lists the accounts whose available balance is below zero, ordered by
+    /// client id and currency, with the overdraft each one is allowed.
+    pub fn negative_balances(&self) -> Vec<NegativeBalance> {
+        let mut negative: Vec<_> = self
+            .accounts()
+            .filter(|acc| acc.available < Decimal::ZERO)
+            .map(|acc| {
+                let limit = self.config.overdrafts.limit(acc.client, acc.currency);
+                NegativeBalance {
+                    client: acc.client,
+                    currency: acc.currency,
+                    available: acc.available,
+                    total: acc.total,
+                    limit,
+                    over_limit: acc.available < -limit,
+                }
+            })
+            .collect();
+        negative.sort_by_key(|row| (row.client, row.currency));
+        negative
+    }
+
+    /// Books a fee charged to, or refunded to, `client` for `txn`, if there
+    /// was one.
+    fn book_fee(
+        &mut self,
+        txn: &Input,
+        client: u16,
+        currency: Option<Currency>,
+        kind: FeeKind,
+        amount: Decimal,
+    ) {
+        if amount > Decimal::ZERO {
+            self.events.push(Event::Fee(FeeEntry {
+                tx: txn.tx,
+                client,
+                currency,
+                r#type: txn.r#type,
+                kind,
+                amount,
+            }));
+        }
+    }
+
+    /// Reports the journal entry `build` makes for a balance movement, if the
+    /// journal is on and the entry moved anything.
+    fn journal(
+        &mut self,
+        tx: u32,
+        movement: Movement,
+        currency: Option<Currency>,
+        build: impl FnOnce(JournalEntry) -> JournalEntry,
+    ) {
+        if !self.config.journal {
+            return;
+        }
+        let mut entry = build(JournalEntry::new(tx, movement, currency));
+        if entry.postings.is_empty() {
+            return;
+        }
+        entry.id = self.journal_entries;
+        self.journal_entries += 1;
+        self.events.push(Event::Journal(entry));
+    }
+
+    pub fn summary(&self) -> &Summary {
+        &self.summary
+    }
+
+    /// Consumes the engine, returning the accounts keyed by client id and
+    /// currency.
+    pub fn into_accounts(self) -> HashMap<(u16, Option<Currency>), Output> {
+        self.accounts
+            .into_values()
+            .flat_map(|client| client.balances)
+            .map(|acc| ((acc.client, acc.currency), acc))
+            .collect()
+    }
+}
+
+/// Applies a dispute, resolve or chargeback: `change` and `fee` to the
+/// holder's `account`, and `credit` to the payer, who is only someone else
+/// for a transfer. Returns whether a balance saturated and the fee actually
+/// charged.
+fn apply_dispute_change(
+    account: &mut Output,
+    payer: Payer<'_>,
+    change: Change,
+    fee: Decimal,
+    credit: Decimal,
+    overdraft: Decimal,
+    overflow: OverflowPolicy,
+) -> Result<(bool, Decimal), TxError> {
+    let Some((payer, client)) = payer else {
+        let (change, fee) = change.with_fee(account, fee, credit, overdraft);
+        return Ok((change.apply_to(account, overflow)?, fee));
+    };
+    let (change, fee) = change.with_fee(account, fee, Decimal::ZERO, overdraft);
+    let credit = Change::available(credit);
+    let (credited, payer_saturated) = payer.preview(client, account.currency, credit, overflow)?;
+    let saturated = change.apply_to(account, overflow)?;
+    payer.commit(credited);
+    Ok((saturated || payer_saturated, fee))
+}
+
+/// The client a disputed transfer was paid by, with their id, when that
+/// isn't the holder.
+type Payer<'a> = Option<(&'a mut Client, u16)>;
+
+/// Splits out the account holding a disputed tx and, when that isn't the
+/// client named on the row, the payer. A disputed transfer is held by
+/// whoever received it, while fee refunds and chargebacks go back to the
+/// payer. Like a transfer, this fails if the receiver is locked, frozen or
+/// closed.
+fn holder_and_payer(
+    accounts: &mut HashMap<u16, Client>,
+    holder: u16,
+    client: u16,
+) -> Result<(&mut Client, Payer<'_>), TxError> {
+    if holder == client {
+        return Ok((
+            accounts.get_mut(&client).expect("opened by try_apply"),
+            None,
+        ));
+    }
+    let [Some(holder), Some(payer)] = accounts.get_disjoint_mut([&holder, &client]) else {
+        unreachable!("both clients were opened by the transfer");
+    };
+    if holder.status != AccountStatus::Active {
+        return Err(TxError::DestinationLocked);
+    }
+    Ok((holder, Some((payer, client))))
+}
+
+/// Locks the account of `client`, who disputed a tx that was charged back.
+fn lock(accounts: &mut HashMap<u16, Client>, client: u16) {
+    if let Some(client) = accounts.get_mut(&client) {
+        client.set_status(AccountStatus::Locked);
+    }
+}
+
+/// Adds the postings for a dispute, resolve or chargeback that applied
+/// `change` to `holder` and credited `credit` to `client`, the payer named on
+/// the row, which included `refund` of an earlier fee, for `fee` charged to
+/// `holder`. What the dispute itself gave or took is left to the caller.
+fn dispute_entry(
+    entry: JournalEntry,
+    client: u16,
+    holder: u16,
+    change: Change,
+    credit: Decimal,
+    refund: Decimal,
+    fee: Decimal,
+) -> JournalEntry {
+    change
+        .post(entry, holder)
+        .client(client, LedgerAccount::Available, credit)
+        .client(holder, LedgerAccount::Available, -fee)
+        .external(LedgerAccount::Fees, refund - fee)
+}
+
+/// Adds `delta` to `balance`, clamping at the bounds under
+/// [`OverflowPolicy::Saturate`] and failing under the others. Returns the sum
+/// and whether it was clamped.
+fn checked_add(
+    balance: Decimal,
+    delta: Decimal,
+    policy: OverflowPolicy,
+) -> Result<(Decimal, bool), TxError> {
+    match balance.checked_add(delta) {
+        Some(sum) => Ok((sum, false)),
+        None if policy == OverflowPolicy::Saturate => Ok((balance.saturating_add(delta), true)),
+        None => Err(TxError::Overflow),
+    }
+}
+
+/// Whether `account` can pay out `amount` plus `fee` without going below
+/// `-overdraft`.
+fn covers(account: &Output, overdraft: Decimal, amount: Decimal, fee: Decimal) -> bool {
+    account
+        .available
+        .checked_add(overdraft)
+        .zip(amount.checked_add(fee))
+        .is_some_and(|(funds, debit)| funds >= debit)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        FeeRule, FeeSchedule, FundsAvailability, HoldRule, InvalidLimit, OverdraftLimit,
+        OverdraftLimits, Reconciliation, TrialBalance,
+    };
+    use rstest::rstest;
+    use rust_decimal_macros::dec;
+
+    fn txn(r#type: InputType, tx: u32, amount: Option<Decimal>) -> Input {
+        Input::new(r#type, 1, tx, amount)
+    }
+
+    #[test]
+    fn test_engine_is_queryable_between_transactions() {
+        let mut engine = Engine::new();
+        assert!(engine.account(1).is_none());
+
+        let applied = engine.apply(Input::new(InputType::Deposit, 1, 1, Some(dec!(10))));
+        assert_eq!(applied, Ok(Applied::Deposit(dec!(10))));
+        assert_eq!(engine.account(1).map(|acc| acc.available), Some(dec!(10)));
+
+        engine
+            .apply(Input::new(InputType::Dispute, 1, 1, None))
+            .unwrap();
+        let acc = engine.account(1).unwrap();
+        assert_eq!((acc.available, acc.held), (dec!(0), dec!(10)));
+        assert_eq!(engine.accounts().count(), 1);
+    }
+
+    #[rstest]
+    #[case::reject(DuplicatePolicy::Reject, Err(TxError::DuplicateTx), dec!(10), dec!(10))]
+    #[case::last_wins(DuplicatePolicy::LastWins, Ok(Applied::Deposit(dec!(20))), dec!(30), dec!(20))]
+    #[case::first_wins(DuplicatePolicy::FirstWins, Ok(Applied::Deposit(dec!(20))), dec!(30), dec!(10))]
+    fn test_duplicate_policy(
+        #[case] duplicates: DuplicatePolicy,
+        #[case] expected: Result<Applied, TxError>,
+        #[case] expected_total: Decimal,
+        #[case] expected_held: Decimal,
+    ) {
+        let mut engine = Engine::with_config(Config {
+            duplicates,
+            ..Default::default()
+        });
+        engine
+            .apply(txn(InputType::Deposit, 1, Some(dec!(10))))
+            .unwrap();
+        let result = engine.apply(txn(InputType::Deposit, 1, Some(dec!(20))));
+        assert_eq!(result, expected);
+        engine.apply(txn(InputType::Dispute, 1, None)).unwrap();
+
+        let acc = engine.account(1).unwrap();
+        assert_eq!((acc.total, acc.held), (expected_total, expected_held));
+        assert_eq!(engine.summary().duplicates, 1);
+    }
+
+    #[test]
+    fn test_last_wins_keeps_disputed_tx() {
+        let mut engine = Engine::with_config(Config {
+            duplicates: DuplicatePolicy::LastWins,
+            ..Default::default()
+        });
+        engine
+            .apply(txn(InputType::Deposit, 1, Some(dec!(10))))
+            .unwrap();
+        engine.apply(txn(InputType::Dispute, 1, None)).unwrap();
+        let result = engine.apply(txn(InputType::Deposit, 1, Some(dec!(20))));
+        assert_eq!(result, Err(TxError::DuplicateTx));
+    }
+
+    #[rstest]
+    #[case::deposit_hold(DisputePolicy::Hold, 1, Ok(Applied::Dispute(dec!(10))), dec!(2), dec!(10), dec!(12))]
+    #[case::deposit_reversal(DisputePolicy::ReversalCredit, 1, Ok(Applied::Dispute(dec!(10))), dec!(2), dec!(10), dec!(12))]
+    #[case::deposit_disallow(DisputePolicy::Disallow, 1, Err(TxError::DisputeNotAllowed), dec!(12), dec!(0), dec!(12))]
+    #[case::withdrawal_hold(DisputePolicy::Hold, 2, Ok(Applied::Dispute(dec!(4))), dec!(8), dec!(4), dec!(12))]
+    #[case::withdrawal_reversal(DisputePolicy::ReversalCredit, 2, Ok(Applied::Dispute(dec!(4))), dec!(12), dec!(4), dec!(16))]
+    #[case::withdrawal_disallow(DisputePolicy::Disallow, 2, Err(TxError::DisputeNotAllowed), dec!(12), dec!(0), dec!(12))]
+    fn test_dispute_policy(
+        #[case] policy: DisputePolicy,
+        #[case] disputed_tx: u32,
+        #[case] expected: Result<Applied, TxError>,
+        #[case] expected_available: Decimal,
+        #[case] expected_held: Decimal,
+        #[case] expected_total: Decimal,
+    ) {
+        let mut engine = Engine::with_config(Config {
+            deposit_disputes: policy,
+            withdrawal_disputes: policy,
+            ..Default::default()
+        });
+        engine
+            .apply(txn(InputType::Deposit, 1, Some(dec!(10))))
+            .unwrap();
+        engine
+            .apply(txn(InputType::Withdrawal, 2, Some(dec!(4))))
+            .unwrap();
+        engine
+            .apply(txn(InputType::Deposit, 3, Some(dec!(6))))
+            .unwrap();
+        let result = engine.apply(txn(InputType::Dispute, disputed_tx, None));
+        assert_eq!(result, expected);
+
+        let acc = engine.account(1).unwrap();
+        assert_eq!(
+            (acc.available, acc.held, acc.total),
+            (expected_available, expected_held, expected_total)
+        );
+    }
+
+    #[rstest]
+    #[case::reject(ShortfallPolicy::Reject, Err(TxError::DisputeExceedsAvailable), dec!(2), dec!(0), false)]
+    #[case::allow_negative(ShortfallPolicy::AllowNegative, Ok(Applied::Dispute(dec!(10))), dec!(-8), dec!(10), true)]
+    #[case::cap_hold(ShortfallPolicy::CapHold, Ok(Applied::Dispute(dec!(2))), dec!(0), dec!(2), false)]
+    fn test_shortfall_policy(
+        #[case] shortfall: ShortfallPolicy,
+        #[case] expected: Result<Applied, TxError>,
+        #[case] expected_available: Decimal,
+        #[case] expected_held: Decimal,
+        #[case] expected_flagged: bool,
+    ) {
+        let mut engine = Engine::with_config(Config {
+            shortfall,
+            ..Default::default()
+        });
+        engine
+            .apply(txn(InputType::Deposit, 1, Some(dec!(10))))
+            .unwrap();
+        engine
+            .apply(txn(InputType::Withdrawal, 2, Some(dec!(8))))
+            .unwrap();
+        let result = engine.apply(txn(InputType::Dispute, 1, None));
+        assert_eq!(result, expected);
+
+        let acc = engine.account(1).unwrap();
+        assert_eq!(
+            (acc.available, acc.held, acc.total, acc.flagged),
+            (expected_available, expected_held, dec!(2), expected_flagged)
+        );
+        let events: Vec<_> = engine.drain_events().collect();
+        if expected.is_ok() {
+            assert_eq!(
+                events,
+                vec![Event::Exposure(Exposure {
+                    tx: 1,
+                    client: 1,
+                    currency: None,
+                    disputed: dec!(10),
+                    shortfall: dec!(8),
+                    policy: shortfall,
+                })]
+            );
+        } else {
+            assert!(events.is_empty());
+        }
+
+        // settling the dispute only ever moves what was actually held
+        if expected.is_ok() {
+            engine.apply(txn(InputType::Resolve, 1, None)).unwrap();
+            let acc = engine.account(1).unwrap();
+            assert_eq!(
+                (acc.available, acc.held, acc.total),
+                (dec!(2), dec!(0), dec!(2))
+            );
+        }
+    }
+
+    #[rstest]
+    #[case::saturate(OverflowPolicy::Saturate, Ok(Applied::Deposit(dec!(1))), true)]
+    #[case::reject(OverflowPolicy::Reject, Err(TxError::Overflow), false)]
+    #[case::abort(OverflowPolicy::Abort, Err(TxError::Overflow), false)]
+    fn test_overflow_policy(
+        #[case] overflow: OverflowPolicy,
+        #[case] expected: Result<Applied, TxError>,
+        #[case] expected_saturated: bool,
+    ) {
+        let mut engine = Engine::with_config(Config {
+            overflow,
+            ..Default::default()
+        });
+        engine
+            .apply(txn(InputType::Deposit, 1, Some(Decimal::MAX)))
+            .unwrap();
+        assert_eq!(
+            engine.apply(txn(InputType::Deposit, 2, Some(dec!(1)))),
+            expected
+        );
+
+        let acc = engine.account(1).unwrap();
+        assert_eq!((acc.available, acc.total), (Decimal::MAX, Decimal::MAX));
+        let events: Vec<_> = engine.drain_events().collect();
+        assert_eq!(
+            events == vec![Event::Saturated { tx: 2, client: 1 }],
+            expected_saturated
+        );
+    }
+
+    #[rstest]
+    #[case::saturate(OverflowPolicy::Saturate, Ok(Applied::Chargeback(dec!(5e28))))]
+    #[case::reject(OverflowPolicy::Reject, Err(TxError::Overflow))]
+    fn test_withdrawal_chargeback_overflow(
+        #[case] overflow: OverflowPolicy,
+        #[case] expected: Result<Applied, TxError>,
+    ) {
+        let mut engine = Engine::with_config(Config {
+            withdrawal_disputes: DisputePolicy::Hold,
+            shortfall: ShortfallPolicy::AllowNegative,
+            overflow,
+            ..Default::default()
+        });
+        engine
+            .apply(txn(InputType::Deposit, 1, Some(dec!(5e28))))
+            .unwrap();
+        engine
+            .apply(txn(InputType::Withdrawal, 2, Some(dec!(5e28))))
+            .unwrap();
+        engine.apply(txn(InputType::Dispute, 2, None)).unwrap();
+        engine.drain_events().for_each(drop);
+
+        // the withdrawal coming back on top of the released hold is more
+        // than a decimal can hold
+        assert_eq!(engine.apply(txn(InputType::Chargeback, 2, None)), expected);
+        let events: Vec<_> = engine.drain_events().collect();
+        if expected.is_ok() {
+            assert_eq!(events, vec![Event::Saturated { tx: 2, client: 1 }]);
+        } else {
+            let acc = engine.account(1).unwrap();
+            assert_eq!((acc.available, acc.held), (dec!(-5e28), dec!(5e28)));
+            assert!(events.is_empty());
+        }
+    }
+
+    #[rstest]
+    #[case::unlock_chargeback(
+        AccountStatus::Locked,
+        InputType::Unlock,
+        Ok(Applied::Unlock),
+        AccountStatus::Active
+    )]
+    #[case::unlock_frozen(
+        AccountStatus::Frozen,
+        InputType::Unlock,
+        Ok(Applied::Unlock),
+        AccountStatus::Active
+    )]
+    #[case::unlock_active(
+        AccountStatus::Active,
+        InputType::Unlock,
+        Err(TxError::NotLocked),
+        AccountStatus::Active
+    )]
+    #[case::freeze(
+        AccountStatus::Active,
+        InputType::Freeze,
+        Ok(Applied::Freeze),
+        AccountStatus::Frozen
+    )]
+    #[case::freeze_locked(
+        AccountStatus::Locked,
+        InputType::Freeze,
+        Err(TxError::AccountLocked),
+        AccountStatus::Locked
+    )]
+    #[case::close(
+        AccountStatus::Frozen,
+        InputType::Close,
+        Ok(Applied::Close),
+        AccountStatus::Closed
+    )]
+    #[case::unlock_closed(
+        AccountStatus::Closed,
+        InputType::Unlock,
+        Err(TxError::AccountClosed),
+        AccountStatus::Closed
+    )]
+    fn test_admin_transactions(
+        #[case] initial: AccountStatus,
+        #[case] action: InputType,
+        #[case] expected: Result<Applied, TxError>,
+        #[case] expected_status: AccountStatus,
+    ) {
+        let mut engine = Engine::new();
+        let admin = |r#type, tx| Input {
+            actor: Some("ops@example.com".to_string()),
+            reason: Some("ticket 42".to_string()),
+            ..Input::new(r#type, 1, tx, None)
+        };
+        engine
+            .apply(Input::new(InputType::Deposit, 1, 1, Some(dec!(10))))
+            .unwrap();
+        match initial {
+            AccountStatus::Active => {}
+            AccountStatus::Locked => {
+                engine
+                    .apply(Input::new(InputType::Dispute, 1, 1, None))
+                    .unwrap();
+                engine
+                    .apply(Input::new(InputType::Chargeback, 1, 1, None))
+                    .unwrap();
+            }
+            AccountStatus::Frozen => {
+                engine.apply(admin(InputType::Freeze, 2)).unwrap();
+            }
+            AccountStatus::Closed => {
+                engine.apply(admin(InputType::Close, 2)).unwrap();
+            }
+        }
+        engine.drain_events().for_each(drop);
+
+        assert_eq!(engine.apply(admin(action, 3)), expected);
+        let acc = engine.account(1).unwrap();
+        assert_eq!(acc.status, expected_status);
+        assert_eq!(acc.locked, expected_status != AccountStatus::Active);
+
+        let events: Vec<_> = engine.drain_events().collect();
+        if expected.is_ok() {
+            assert_eq!(
+                events,
+                vec![Event::Audit(AuditRecord {
+                    tx: 3,
+                    client: 1,
+                    action,
+                    actor: "ops@example.com".to_string(),
+                    reason: Some("ticket 42".to_string()),
+                    before: initial,
+                    after: expected_status,
+                })]
+            );
+        } else {
+            assert!(events.is_empty());
+        }
+    }
+
+    #[test]
+    fn test_admin_transactions_need_an_actor() {
+        let mut engine = Engine::new();
+        engine
+            .apply(Input::new(InputType::Deposit, 1, 1, Some(dec!(10))))
+            .unwrap();
+        assert_eq!(
+            engine.apply(Input::new(InputType::Freeze, 1, 2, None)),
+            Err(TxError::MissingActor)
+        );
+        assert!(!engine.account(1).unwrap().locked);
+    }
+
+    #[test]
+    fn test_unlocked_account_accepts_transactions_again() {
+        let mut engine = Engine::new();
+        engine
+            .apply(Input::new(InputType::Deposit, 1, 1, Some(dec!(10))))
+            .unwrap();
+        engine
+            .apply(Input::new(InputType::Dispute, 1, 1, None))
+            .unwrap();
+        engine
+            .apply(Input::new(InputType::Chargeback, 1, 1, None))
+            .unwrap();
+        assert_eq!(
+            engine.apply(Input::new(InputType::Deposit, 1, 2, Some(dec!(5)))),
+            Err(TxError::AccountLocked)
+        );
+
+        let unlock = Input {
+            actor: Some("ops".to_string()),
+            ..Input::new(InputType::Unlock, 1, 3, None)
+        };
+        engine.apply(unlock).unwrap();
+        assert_eq!(
+            engine.apply(Input::new(InputType::Deposit, 1, 4, Some(dec!(5)))),
+            Ok(Applied::Deposit(dec!(5)))
+        );
+    }
+
+    #[rstest]
+    #[case::partial_dispute(vec![(InputType::Dispute, Some(dec!(4)))], Ok(Applied::Dispute(dec!(4))), dec!(6), dec!(4), dec!(10))]
+    #[case::second_partial_dispute(vec![(InputType::Dispute, Some(dec!(4))), (InputType::Dispute, Some(dec!(6)))], Ok(Applied::Dispute(dec!(6))), dec!(0), dec!(10), dec!(10))]
+    #[case::dispute_above_original(vec![(InputType::Dispute, Some(dec!(11)))], Err(TxError::ExceedsOriginal), dec!(10), dec!(0), dec!(10))]
+    #[case::dispute_above_remainder(vec![(InputType::Dispute, Some(dec!(4))), (InputType::Dispute, Some(dec!(7)))], Err(TxError::ExceedsOriginal), dec!(6), dec!(4), dec!(10))]
+    #[case::dispute_negative(vec![(InputType::Dispute, Some(dec!(-1)))], Err(TxError::InvalidAmount), dec!(10), dec!(0), dec!(10))]
+    #[case::partial_resolve(vec![(InputType::Dispute, Some(dec!(4))), (InputType::Resolve, Some(dec!(1)))], Ok(Applied::Resolve(dec!(1))), dec!(7), dec!(3), dec!(10))]
+    #[case::resolve_rest(vec![(InputType::Dispute, Some(dec!(4))), (InputType::Resolve, Some(dec!(1))), (InputType::Resolve, None)], Ok(Applied::Resolve(dec!(3))), dec!(10), dec!(0), dec!(10))]
+    #[case::resolve_above_disputed(vec![(InputType::Dispute, Some(dec!(4))), (InputType::Resolve, Some(dec!(5)))], Err(TxError::ExceedsDisputed), dec!(6), dec!(4), dec!(10))]
+    #[case::partial_chargeback(vec![(InputType::Dispute, Some(dec!(4))), (InputType::Chargeback, Some(dec!(3)))], Ok(Applied::Chargeback(dec!(3))), dec!(6), dec!(1), dec!(7))]
+    fn test_partial_disputes(
+        #[case] txns: Vec<(InputType, Option<Decimal>)>,
+        #[case] expected_last: Result<Applied, TxError>,
+        #[case] expected_available: Decimal,
+        #[case] expected_held: Decimal,
+        #[case] expected_total: Decimal,
+    ) {
+        let mut engine = Engine::new();
+        engine
+            .apply(Input::new(InputType::Deposit, 1, 1, Some(dec!(10))))
+            .unwrap();
+        let mut results: Vec<_> = txns
+            .into_iter()
+            .map(|(r#type, amount)| engine.apply(Input::new(r#type, 1, 1, amount)))
+            .collect();
+        assert_eq!(results.pop(), Some(expected_last));
+        assert!(results.iter().all(Result::is_ok));
+
+        let acc = engine.account(1).unwrap();
+        assert_eq!(
+            (acc.available, acc.held, acc.total),
+            (expected_available, expected_held, expected_total)
+        );
+    }
+
+    fn timed(r#type: InputType, tx: u32, amount: Option<Decimal>, timestamp: u64) -> Input {
+        Input {
+            timestamp: Some(timestamp),
+            ..Input::new(r#type, 1, tx, amount)
+        }
+    }
+
+    #[rstest]
+    #[case::inside_window(Some(100), Some(600), Ok(Applied::Dispute(dec!(10))))]
+    #[case::on_the_deadline(Some(100), Some(1100), Ok(Applied::Dispute(dec!(10))))]
+    #[case::after_window(Some(100), Some(1101), Err(TxError::DisputeWindowClosed))]
+    #[case::untimed_deposit(None, Some(5000), Ok(Applied::Dispute(dec!(10))))]
+    #[case::untimed_dispute(Some(100), None, Ok(Applied::Dispute(dec!(10))))]
+    fn test_dispute_window(
+        #[case] deposited_at: Option<u64>,
+        #[case] disputed_at: Option<u64>,
+        #[case] expected: Result<Applied, TxError>,
+    ) {
+        let mut engine = Engine::with_config(Config {
+            dispute_window: Some(1000),
+            ..Config::default()
+        });
+        engine
+            .apply(Input {
+                timestamp: deposited_at,
+                ..Input::new(InputType::Deposit, 1, 1, Some(dec!(10)))
+            })
+            .unwrap();
+        let dispute = Input {
+            timestamp: disputed_at,
+            ..Input::new(InputType::Dispute, 1, 1, None)
+        };
+        assert_eq!(engine.apply(dispute), expected);
+    }
+
+    #[test]
+    fn test_dispute_timeout() {
+        let mut engine = Engine::with_config(Config {
+            dispute_timeout: Some(60),
+            ..Config::default()
+        });
+        engine
+            .apply(timed(InputType::Deposit, 1, Some(dec!(10)), 0))
+            .unwrap();
+        engine
+            .apply(timed(InputType::Dispute, 1, Some(dec!(4)), 10))
+            .unwrap();
+        engine
+            .apply(timed(InputType::Dispute, 1, Some(dec!(2)), 30))
+            .unwrap();
+        engine.drain_events().for_each(drop);
+
+        // still open one second before the deadline
+        engine.advance_clock(69);
+        assert!(engine.drain_events().next().is_none());
+        assert_eq!(engine.account(1).unwrap().held, dec!(6));
+
+        // the clock is driven by the next timestamped row, whatever its client
+        let other = Input {
+            timestamp: Some(70),
+            ..Input::new(InputType::Deposit, 2, 2, Some(dec!(1)))
+        };
+        engine.apply(other).unwrap();
+        assert_eq!(
+            engine.drain_events().collect::<Vec<_>>(),
+            vec![Event::DisputeExpired(ExpiredDispute {
+                tx: 1,
+                client: 1,
+                currency: None,
+                disputed: dec!(6),
+                released: dec!(6),
+                opened_at: 10,
+                expired_at: 70,
+            })]
+        );
+        let acc = engine.account(1).unwrap();
+        assert_eq!((acc.available, acc.held), (dec!(10), dec!(0)));
+        assert_eq!(
+            engine.apply(timed(InputType::Resolve, 1, None, 71)),
+            Err(TxError::NotDisputed)
+        );
+
+        // a disputed transfer is released on the receiving side
+        let transfer = Input {
+            timestamp: Some(80),
+            ..transfer(3, Some(2), dec!(4))
+        };
+        engine.apply(transfer).unwrap();
+        engine
+            .apply(timed(InputType::Dispute, 3, None, 90))
+            .unwrap();
+        engine.drain_events().for_each(drop);
+        engine.advance_clock(150);
+        assert_eq!(
+            engine.drain_events().collect::<Vec<_>>(),
+            vec![Event::DisputeExpired(ExpiredDispute {
+                tx: 3,
+                client: 2,
+                currency: None,
+                disputed: dec!(4),
+                released: dec!(4),
+                opened_at: 90,
+                expired_at: 150,
+            })]
+        );
+        let payer = engine.account(1).unwrap();
+        assert_eq!((payer.available, payer.held), (dec!(6), dec!(0)));
+        let receiver = engine.account(2).unwrap();
+        assert_eq!((receiver.available, receiver.held), (dec!(5), dec!(0)));
+    }
+
+    #[test]
+    fn test_resolved_dispute_does_not_expire() {
+        let mut engine = Engine::with_config(Config {
+            dispute_timeout: Some(60),
+            ..Config::default()
+        });
+        engine
+            .apply(timed(InputType::Deposit, 1, Some(dec!(10)), 0))
+            .unwrap();
+        engine
+            .apply(timed(InputType::Dispute, 1, None, 10))
+            .unwrap();
+        engine
+            .apply(timed(InputType::Resolve, 1, None, 20))
+            .unwrap();
+        // disputed again later, so only the second dispute's deadline counts
+        engine
+            .apply(timed(InputType::Dispute, 1, None, 50))
+            .unwrap();
+        engine.drain_events().for_each(drop);
+
+        engine.advance_clock(100);
+        assert!(engine.drain_events().next().is_none());
+        assert_eq!(engine.account(1).unwrap().held, dec!(10));
+        engine.advance_clock(110);
+        assert_eq!(engine.drain_events().count(), 1);
+        assert_eq!(engine.account(1).unwrap().held, dec!(0));
+    }
+
+    #[test]
+    fn test_expired_partial_chargeback_locks() {
+        let mut engine = Engine::with_config(Config {
+            dispute_timeout: Some(60),
+            ..Config::default()
+        });
+        engine
+            .apply(timed(InputType::Deposit, 1, Some(dec!(10)), 0))
+            .unwrap();
+        engine
+            .apply(timed(InputType::Dispute, 1, Some(dec!(4)), 10))
+            .unwrap();
+        engine
+            .apply(timed(InputType::Chargeback, 1, Some(dec!(3)), 20))
+            .unwrap();
+        assert!(!engine.account(1).unwrap().locked);
+
+        engine.advance_clock(70);
+        let acc = engine.account(1).unwrap();
+        assert_eq!(
+            (acc.available, acc.held, acc.locked),
+            (dec!(7), dec!(0), true)
+        );
+        assert_eq!(engine.tx_state(1), Some(TxState::ChargedBack));
+    }
+
+    #[test]
+    fn test_overflowing_expiry_is_retried() {
+        let mut engine = Engine::with_config(Config {
+            dispute_timeout: Some(60),
+            overflow: OverflowPolicy::Reject,
+            ..Config::default()
+        });
+        engine
+            .apply(timed(InputType::Deposit, 1, Some(dec!(10)), 0))
+            .unwrap();
+        engine
+            .apply(timed(InputType::Dispute, 1, None, 10))
+            .unwrap();
+        engine.drain_events().for_each(drop);
+        // no transaction gets a balance here, so it is set up by hand
+        let account = engine.accounts.get_mut(&1).unwrap().balance_mut(1, None);
+        account.available = Decimal::MAX;
+
+        // releasing the hold would overflow available, so the dispute stays
+        // open and comes up again at the next sweep
+        engine.advance_clock(70);
+        assert_eq!(
+            engine.drain_events().collect::<Vec<_>>(),
+            vec![Event::Deferred { tx: 1, client: 1 }]
+        );
+        engine.advance_clock(71);
+        assert_eq!(engine.drain_events().count(), 1);
+        assert_eq!(engine.account(1).unwrap().held, dec!(10));
+
+        let account = engine.accounts.get_mut(&1).unwrap().balance_mut(1, None);
+        account.available = Decimal::ZERO;
+        engine.advance_clock(72);
+        assert!(matches!(
+            engine.drain_events().collect::<Vec<_>>()[..],
+            [Event::DisputeExpired(_)]
+        ));
+        assert_eq!(engine.account(1).unwrap().held, dec!(0));
+    }
+
+    #[rstest]
+    #[case::settled(vec![], Ok(Applied::Deposit(dec!(10))), TxState::Settled)]
+    #[case::disputed(vec![InputType::Dispute], Ok(Applied::Dispute(dec!(10))), TxState::Disputed)]
+    #[case::resolved(vec![InputType::Dispute, InputType::Resolve], Ok(Applied::Resolve(dec!(10))), TxState::Resolved)]
+    #[case::redisputed(vec![InputType::Dispute, InputType::Resolve, InputType::Dispute], Ok(Applied::Dispute(dec!(10))), TxState::Disputed)]
+    #[case::redispute_limit(vec![InputType::Dispute, InputType::Resolve, InputType::Dispute, InputType::Resolve, InputType::Dispute], Err(TxError::RedisputeLimit), TxState::Resolved)]
+    #[case::charged_back(vec![InputType::Dispute, InputType::Chargeback], Ok(Applied::Chargeback(dec!(10))), TxState::ChargedBack)]
+    #[case::dispute_after_chargeback(vec![InputType::Dispute, InputType::Chargeback, InputType::Dispute], Err(TxError::ChargedBack), TxState::ChargedBack)]
+    #[case::resolve_after_chargeback(vec![InputType::Dispute, InputType::Chargeback, InputType::Resolve], Err(TxError::ChargedBack), TxState::ChargedBack)]
+    fn test_tx_lifecycle(
+        #[case] actions: Vec<InputType>,
+        #[case] expected_last: Result<Applied, TxError>,
+        #[case] expected_state: TxState,
+    ) {
+        let mut engine = Engine::with_config(Config {
+            redispute_limit: Some(1),
+            ..Config::default()
+        });
+        let mut results = vec![engine.apply(Input::new(InputType::Deposit, 1, 1, Some(dec!(10))))];
+        for r#type in actions {
+            // lift the chargeback lock, so only the tx state decides
+            if engine.account(1).unwrap().locked {
+                let unlock = Input {
+                    actor: Some("ops".to_string()),
+                    ..Input::new(InputType::Unlock, 1, 2, None)
+                };
+                engine.apply(unlock).unwrap();
+            }
+            results.push(engine.apply(Input::new(r#type, 1, 1, None)));
+        }
+        assert_eq!(results.pop(), Some(expected_last));
+        assert!(results.iter().all(Result::is_ok));
+        assert_eq!(engine.tx_state(1), Some(expected_state));
+    }
+
+    #[test]
+    fn test_partial_chargeback_closes_tx() {
+        let mut engine = Engine::new();
+        engine
+            .apply(txn(InputType::Deposit, 1, Some(dec!(10))))
+            .unwrap();
+        engine
+            .apply(txn(InputType::Dispute, 1, Some(dec!(4))))
+            .unwrap();
+        engine
+            .apply(txn(InputType::Chargeback, 1, Some(dec!(3))))
+            .unwrap();
+        // the rest of the dispute can still be settled, and the account is
+        // only locked once it is...
+        assert_eq!(engine.tx_state(1), Some(TxState::Disputed));
+        assert!(!engine.account(1).unwrap().locked);
+        assert_eq!(
+            engine.apply(txn(InputType::Resolve, 1, None)),
+            Ok(Applied::Resolve(dec!(1)))
+        );
+        let acc = engine.account(1).unwrap();
+        assert_eq!(
+            (acc.available, acc.held, acc.locked),
+            (dec!(7), dec!(0), true)
+        );
+        // ...and the undisputed remainder can't be disputed any more
+        assert_eq!(engine.tx_state(1), Some(TxState::ChargedBack));
+        let unlock = Input {
+            actor: Some("ops".to_string()),
+            ..Input::new(InputType::Unlock, 1, 2, None)
+        };
+        engine.apply(unlock).unwrap();
+        assert_eq!(
+            engine.apply(txn(InputType::Dispute, 1, None)),
+            Err(TxError::ChargedBack)
+        );
+    }
+
+    #[rstest]
+    #[case::deposit_hold(DisputePolicy::Hold, 2, Ok(Applied::Chargeback(dec!(5))), dec!(6), dec!(6))]
+    #[case::deposit_reversal(DisputePolicy::ReversalCredit, 2, Ok(Applied::Chargeback(dec!(5))), dec!(6), dec!(6))]
+    #[case::withdrawal_hold(DisputePolicy::Hold, 3, Ok(Applied::Chargeback(dec!(4))), dec!(15), dec!(15))]
+    #[case::withdrawal_reversal(DisputePolicy::ReversalCredit, 3, Ok(Applied::Chargeback(dec!(4))), dec!(15), dec!(15))]
+    fn test_chargeback_direction(
+        #[case] policy: DisputePolicy,
+        #[case] disputed_tx: u32,
+        #[case] expected: Result<Applied, TxError>,
+        #[case] expected_available: Decimal,
+        #[case] expected_total: Decimal,
+    ) {
+        let mut engine = Engine::with_config(Config {
+            deposit_disputes: policy,
+            withdrawal_disputes: policy,
+            ..Default::default()
+        });
+        engine
+            .apply(txn(InputType::Deposit, 1, Some(dec!(10))))
+            .unwrap();
+        engine
+            .apply(txn(InputType::Deposit, 2, Some(dec!(5))))
+            .unwrap();
+        engine
+            .apply(txn(InputType::Withdrawal, 3, Some(dec!(4))))
+            .unwrap();
+        engine
+            .apply(txn(InputType::Dispute, disputed_tx, None))
+            .unwrap();
+        let result = engine.apply(txn(InputType::Chargeback, disputed_tx, None));
+        assert_eq!(result, expected);
+
+        let acc = engine.account(1).unwrap();
+        assert_eq!(
+            (acc.available, acc.held, acc.total, acc.locked),
+            (expected_available, dec!(0), expected_total, true)
+        );
+    }
+
+    #[test]
+    fn test_balances_are_kept_per_currency() {
+        let mut engine = Engine::new();
+        let txn = |r#type, tx, amount, currency: Option<&str>| Input {
+            currency: currency.map(|code| code.parse().unwrap()),
+            ..Input::new(r#type, 1, tx, amount)
+        };
+        engine
+            .apply(txn(InputType::Deposit, 1, Some(dec!(10)), Some("EUR")))
+            .unwrap();
+        engine
+            .apply(txn(InputType::Deposit, 2, Some(dec!(5)), Some("usd")))
+            .unwrap();
+        assert_eq!(
+            engine.apply(txn(InputType::Withdrawal, 3, Some(dec!(7)), Some("USD"))),
+            Err(TxError::InsufficientFunds)
+        );
+        assert_eq!(
+            engine.apply(txn(InputType::Dispute, 2, None, Some("EUR"))),
+            Err(TxError::CurrencyMismatch)
+        );
+        // without a currency, the dispute applies in the tx's own currency
+        engine
+            .apply(txn(InputType::Dispute, 2, None, None))
+            .unwrap();
+        engine
+            .apply(txn(InputType::Chargeback, 2, None, Some("USD")))
+            .unwrap();
+
+        let eur = engine.account_in(1, Some("EUR".parse().unwrap())).unwrap();
+        assert_eq!(
+            (eur.available, eur.total, eur.locked),
+            (dec!(10), dec!(10), true)
+        );
+        let usd = engine.account_in(1, Some("USD".parse().unwrap())).unwrap();
+        assert_eq!(
+            (usd.available, usd.total, usd.locked),
+            (dec!(0), dec!(0), true)
+        );
+        assert!(engine.account(1).is_none());
+        assert_eq!(engine.accounts().count(), 2);
+        // the chargeback locks the client in every currency
+        assert_eq!(
+            engine.apply(txn(InputType::Deposit, 4, Some(dec!(1)), Some("EUR"))),
+            Err(TxError::AccountLocked)
+        );
+    }
+
+    fn fee_schedule() -> FeeSchedule {
+        let mut fees = FeeSchedule::new();
+        for (r#type, flat) in [
+            (InputType::Withdrawal, dec!(1)),
+            (InputType::Chargeback, dec!(2)),
+        ] {
+            fees.insert(FeeRule {
+                r#type,
+                from: None,
+                flat: Some(flat),
+                percent: None,
+                min: None,
+                max: None,
+            })
+            .unwrap();
+        }
+        fees
+    }
+
+    #[rstest]
+    #[case::never(FeeRefundPolicy::Never, dec!(89), dec!(97), vec![(2, FeeKind::Charge, dec!(1)), (2, FeeKind::Charge, dec!(2))])]
+    #[case::on_dispute(FeeRefundPolicy::OnDispute, dec!(90), dec!(98), vec![(2, FeeKind::Charge, dec!(1)), (2, FeeKind::Refund, dec!(1)), (2, FeeKind::Charge, dec!(2))])]
+    #[case::on_chargeback(FeeRefundPolicy::OnChargeback, dec!(89), dec!(98), vec![(2, FeeKind::Charge, dec!(1)), (2, FeeKind::Refund, dec!(1)), (2, FeeKind::Charge, dec!(2))])]
+    fn test_fees(
+        #[case] fee_refunds: FeeRefundPolicy,
+        #[case] expected_disputed_available: Decimal,
+        #[case] expected_available: Decimal,
+        #[case] expected_ledger: Vec<(u32, FeeKind, Decimal)>,
+    ) {
+        let mut engine = Engine::with_config(Config {
+            withdrawal_disputes: DisputePolicy::ReversalCredit,
+            fees: fee_schedule(),
+            fee_refunds,
+            ..Config::default()
+        });
+        engine
+            .apply(txn(InputType::Deposit, 1, Some(dec!(100))))
+            .unwrap();
+        engine
+            .apply(txn(InputType::Withdrawal, 2, Some(dec!(10))))
+            .unwrap();
+        assert_eq!(engine.account(1).unwrap().available, dec!(89));
+        engine.apply(txn(InputType::Dispute, 2, None)).unwrap();
+        assert_eq!(
+            engine.account(1).unwrap().available,
+            expected_disputed_available
+        );
+        engine.apply(txn(InputType::Chargeback, 2, None)).unwrap();
+
+        let acc = engine.account(1).unwrap();
+        assert_eq!(
+            (acc.available, acc.held, acc.total),
+            (expected_available, dec!(0), expected_available)
+        );
+        let ledger: Vec<_> = engine
+            .drain_events()
+            .filter_map(|event| match event {
+                Event::Fee(entry) => Some((entry.tx, entry.kind, entry.amount)),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(ledger, expected_ledger);
+    }
+
+    #[test]
+    fn test_withdrawal_must_cover_its_fee() {
+        let mut engine = Engine::with_config(Config {
+            fees: fee_schedule(),
+            ..Config::default()
+        });
+        engine
+            .apply(txn(InputType::Deposit, 1, Some(dec!(10))))
+            .unwrap();
+        assert_eq!(
+            engine.apply(txn(InputType::Withdrawal, 2, Some(dec!(9.5)))),
+            Err(TxError::InsufficientFunds)
+        );
+        engine
+            .apply(txn(InputType::Withdrawal, 3, Some(dec!(9))))
+            .unwrap();
+        assert_eq!(engine.account(1).unwrap().available, dec!(0));
+
+        // the chargeback fee is capped at what is left
+        engine
+            .apply(txn(InputType::Deposit, 4, Some(dec!(1))))
+            .unwrap();
+        engine.apply(txn(InputType::Dispute, 4, None)).unwrap();
+        engine.apply(txn(InputType::Chargeback, 4, None)).unwrap();
+        let acc = engine.account(1).unwrap();
+        assert_eq!((acc.available, acc.total), (dec!(0), dec!(0)));
+    }
+
+    #[test]
+    fn test_overdraft() {
+        let mut overdrafts = OverdraftLimits::new();
+        let limit = |client, limit| OverdraftLimit {
+            client,
+            currency: None,
+            limit,
+        };
+        overdrafts.insert(limit(1, dec!(50))).unwrap();
+        assert_eq!(
+            overdrafts.insert(limit(2, dec!(-1))),
+            Err(InvalidLimit(limit(2, dec!(-1))))
+        );
+        let mut engine = Engine::with_config(Config {
+            fees: fee_schedule(),
+            overdrafts,
+            ..Config::default()
+        });
+        for client in [1, 2] {
+            engine
+                .apply(Input::new(
+                    InputType::Deposit,
+                    client,
+                    client.into(),
+                    Some(dec!(10)),
+                ))
+                .unwrap();
+        }
+
+        // the withdrawal and its fee use up all but 1 of the credit line
+        engine
+            .apply(Input::new(InputType::Withdrawal, 1, 3, Some(dec!(58))))
+            .unwrap();
+        assert_eq!(engine.account(1).unwrap().available, dec!(-49));
+        assert_eq!(
+            engine.apply(Input::new(InputType::Withdrawal, 1, 4, Some(dec!(1)))),
+            Err(TxError::InsufficientFunds)
+        );
+        engine.apply(transfer(5, Some(2), dec!(1))).unwrap();
+        assert_eq!(
+            engine.apply(Input::new(InputType::Withdrawal, 2, 6, Some(dec!(11)))),
+            Err(TxError::InsufficientFunds)
+        );
+
+        assert_eq!(
+            engine.negative_balances(),
+            vec![NegativeBalance {
+                client: 1,
+                currency: None,
+                available: dec!(-50),
+                total: dec!(-50),
+                limit: dec!(50),
+                over_limit: false,
+            }]
+        );
+    }
+
+    fn transfer(tx: u32, to: Option<u16>, amount: Decimal) -> Input {
+        Input {
+            destination: to,
+            ..Input::new(InputType::Transfer, 1, tx, Some(amount))
+        }
+    }
+
+    fn freeze(client: u16) -> Input {
+        Input {
+            actor: Some("ops".to_string()),
+            ..Input::new(InputType::Freeze, client, 90, None)
+        }
+    }
+
+    fn close(client: u16) -> Input {
+        Input {
+            actor: Some("ops".to_string()),
+            ..Input::new(InputType::Close, client, 91, None)
+        }
+    }
+
+    #[rstest]
+    #[case::transfer(vec![transfer(3, Some(2), dec!(4))], Ok(Applied::Transfer(dec!(4))), dec!(6), (dec!(5), dec!(0), dec!(5)))]
+    #[case::insufficient(vec![transfer(3, Some(2), dec!(11))], Err(TxError::InsufficientFunds), dec!(10), (dec!(1), dec!(0), dec!(1)))]
+    #[case::missing_destination(vec![transfer(3, None, dec!(4))], Err(TxError::MissingDestination), dec!(10), (dec!(1), dec!(0), dec!(1)))]
+    #[case::to_self(vec![transfer(3, Some(1), dec!(4))], Err(TxError::InvalidDestination), dec!(10), (dec!(1), dec!(0), dec!(1)))]
+    #[case::negative(vec![transfer(3, Some(2), dec!(-4))], Err(TxError::InvalidAmount), dec!(10), (dec!(1), dec!(0), dec!(1)))]
+    #[case::destination_locked(vec![freeze(2), transfer(3, Some(2), dec!(4))], Err(TxError::DestinationLocked), dec!(10), (dec!(1), dec!(0), dec!(1)))]
+    #[case::duplicate(vec![transfer(2, Some(2), dec!(4))], Err(TxError::DuplicateTx), dec!(10), (dec!(1), dec!(0), dec!(1)))]
+    #[case::dispute_holds_receiver(vec![transfer(3, Some(2), dec!(4)), Input::new(InputType::Dispute, 1, 3, None)], Ok(Applied::Dispute(dec!(4))), dec!(6), (dec!(1), dec!(4), dec!(5)))]
+    #[case::receiver_cant_dispute(vec![transfer(3, Some(2), dec!(4)), Input::new(InputType::Dispute, 2, 3, None)], Err(TxError::ClientMismatch), dec!(6), (dec!(5), dec!(0), dec!(5)))]
+    #[case::resolve(vec![transfer(3, Some(2), dec!(4)), Input::new(InputType::Dispute, 1, 3, None), Input::new(InputType::Resolve, 1, 3, None)], Ok(Applied::Resolve(dec!(4))), dec!(6), (dec!(5), dec!(0), dec!(5)))]
+    #[case::chargeback(vec![transfer(3, Some(2), dec!(4)), Input::new(InputType::Dispute, 1, 3, None), Input::new(InputType::Chargeback, 1, 3, None)], Ok(Applied::Chargeback(dec!(4))), dec!(10), (dec!(1), dec!(0), dec!(1)))]
+    #[case::dispute_receiver_closed(vec![transfer(3, Some(2), dec!(4)), close(2), Input::new(InputType::Dispute, 1, 3, None)], Err(TxError::DestinationLocked), dec!(6), (dec!(5), dec!(0), dec!(5)))]
+    #[case::chargeback_receiver_frozen(vec![transfer(3, Some(2), dec!(4)), Input::new(InputType::Dispute, 1, 3, None), freeze(2), Input::new(InputType::Chargeback, 1, 3, None)], Err(TxError::DestinationLocked), dec!(6), (dec!(1), dec!(4), dec!(5)))]
+    fn test_transfers(
+        #[case] txns: Vec<Input>,
+        #[case] expected_last: Result<Applied, TxError>,
+        #[case] expected_sender_total: Decimal,
+        #[case] expected_receiver: (Decimal, Decimal, Decimal),
+    ) {
+        let mut engine = Engine::new();
+        engine
+            .apply(Input::new(InputType::Deposit, 1, 1, Some(dec!(10))))
+            .unwrap();
+        engine
+            .apply(Input::new(InputType::Deposit, 2, 2, Some(dec!(1))))
+            .unwrap();
+        let mut results: Vec<_> = txns.into_iter().map(|txn| engine.apply(txn)).collect();
+        assert_eq!(results.pop(), Some(expected_last));
+        assert!(results.iter().all(Result::is_ok));
+
+        let sender = engine.account(1).unwrap();
+        assert_eq!(
+            (sender.available, sender.total),
+            (expected_sender_total, expected_sender_total)
+        );
+        let receiver = engine.account(2).unwrap();
+        assert_eq!(
+            (receiver.available, receiver.held, receiver.total),
+            expected_receiver
+        );
+    }
+
+    #[test]
+    fn test_transfer_chargeback_locks_sender() {
+        let mut engine = Engine::new();
+        engine
+            .apply(Input::new(InputType::Deposit, 1, 1, Some(dec!(10))))
+            .unwrap();
+        engine.apply(transfer(2, Some(2), dec!(4))).unwrap();
+        engine
+            .apply(Input::new(InputType::Dispute, 1, 2, None))
+            .unwrap();
+        engine
+            .apply(Input::new(InputType::Chargeback, 1, 2, None))
+            .unwrap();
+        // the sender disputed the transfer, so it's their account that is
+        // locked, while the receiver only gave the funds back
+        assert!(engine.account(1).unwrap().locked);
+        assert!(!engine.account(2).unwrap().locked);
+        assert_eq!(
+            engine.apply(Input::new(InputType::Deposit, 2, 3, Some(dec!(1)))),
+            Ok(Applied::Deposit(dec!(1)))
+        );
+    }
+
+    #[test]
+    fn test_transfer_is_atomic() {
+        let mut engine = Engine::with_config(Config {
+            overflow: OverflowPolicy::Reject,
+            ..Config::default()
+        });
+        engine
+            .apply(Input::new(InputType::Deposit, 1, 1, Some(dec!(10))))
+            .unwrap();
+        engine
+            .apply(Input::new(InputType::Deposit, 2, 2, Some(Decimal::MAX)))
+            .unwrap();
+        // the credit would overflow, so the debit doesn't happen either
+        assert_eq!(
+            engine.apply(transfer(3, Some(2), dec!(4))),
+            Err(TxError::Overflow)
+        );
+        assert_eq!(engine.account(1).unwrap().available, dec!(10));
+        assert_eq!(engine.account(2).unwrap().available, Decimal::MAX);
+    }
+
+    fn auth(r#type: InputType, tx: u32, amount: Option<Decimal>) -> Input {
+        Input::new(r#type, 1, tx, amount)
+    }
+
+    #[rstest]
+    #[case::authorize(vec![auth(InputType::Authorize, 2, Some(dec!(6)))], Ok(Applied::Authorize(dec!(6))), (dec!(4), dec!(6), dec!(10)))]
+    #[case::capture(vec![auth(InputType::Authorize, 2, Some(dec!(6))), auth(InputType::Capture, 2, None)], Ok(Applied::Capture(dec!(6))), (dec!(4), dec!(0), dec!(4)))]
+    #[case::partial_capture(vec![auth(InputType::Authorize, 2, Some(dec!(6))), auth(InputType::Capture, 2, Some(dec!(4)))], Ok(Applied::Capture(dec!(4))), (dec!(4), dec!(2), dec!(6)))]
+    #[case::void_after_partial_capture(vec![auth(InputType::Authorize, 2, Some(dec!(6))), auth(InputType::Capture, 2, Some(dec!(4))), auth(InputType::Void, 2, None)], Ok(Applied::Void(dec!(2))), (dec!(6), dec!(0), dec!(6)))]
+    #[case::void(vec![auth(InputType::Authorize, 2, Some(dec!(6))), auth(InputType::Void, 2, None)], Ok(Applied::Void(dec!(6))), (dec!(10), dec!(0), dec!(10)))]
+    #[case::capture_after_void(vec![auth(InputType::Authorize, 2, Some(dec!(6))), auth(InputType::Void, 2, None), auth(InputType::Capture, 2, None)], Err(TxError::UnknownAuth), (dec!(10), dec!(0), dec!(10)))]
+    #[case::capture_after_full_capture(vec![auth(InputType::Authorize, 2, Some(dec!(6))), auth(InputType::Capture, 2, None), auth(InputType::Capture, 2, None)], Err(TxError::UnknownAuth), (dec!(4), dec!(0), dec!(4)))]
+    #[case::over_capture(vec![auth(InputType::Authorize, 2, Some(dec!(6))), auth(InputType::Capture, 2, Some(dec!(7)))], Err(TxError::ExceedsAuthorized), (dec!(4), dec!(6), dec!(10)))]
+    #[case::insufficient(vec![auth(InputType::Authorize, 2, Some(dec!(11)))], Err(TxError::InsufficientFunds), (dec!(10), dec!(0), dec!(10)))]
+    #[case::duplicate(vec![auth(InputType::Authorize, 1, Some(dec!(6)))], Err(TxError::DuplicateTx), (dec!(10), dec!(0), dec!(10)))]
+    #[case::deposit_reusing_auth_id(vec![auth(InputType::Authorize, 2, Some(dec!(6))), auth(InputType::Deposit, 2, Some(dec!(1)))], Err(TxError::DuplicateTx), (dec!(4), dec!(6), dec!(10)))]
+    #[case::other_client(vec![auth(InputType::Authorize, 2, Some(dec!(6))), Input::new(InputType::Capture, 2, 2, None)], Err(TxError::ClientMismatch), (dec!(4), dec!(6), dec!(10)))]
+    #[case::dispute_capture(vec![auth(InputType::Authorize, 2, Some(dec!(6))), auth(InputType::Capture, 2, Some(dec!(4))), auth(InputType::Capture, 2, Some(dec!(2))), auth(InputType::Dispute, 2, None)], Ok(Applied::Dispute(dec!(6))), (dec!(4), dec!(6), dec!(10)))]
+    fn test_authorizations(
+        #[case] txns: Vec<Input>,
+        #[case] expected_last: Result<Applied, TxError>,
+        #[case] expected: (Decimal, Decimal, Decimal),
+    ) {
+        let mut engine = Engine::with_config(Config {
+            withdrawal_disputes: DisputePolicy::ReversalCredit,
+            ..Config::default()
+        });
+        engine
+            .apply(auth(InputType::Deposit, 1, Some(dec!(10))))
+            .unwrap();
+        let mut results: Vec<_> = txns.into_iter().map(|txn| engine.apply(txn)).collect();
+        assert_eq!(results.pop(), Some(expected_last));
+        assert!(results.iter().all(Result::is_ok));
+
+        let acc = engine.account(1).unwrap();
+        assert_eq!((acc.available, acc.held, acc.total), expected);
+    }
+
+    #[test]
+    fn test_auth_expiry() {
+        let mut engine = Engine::with_config(Config {
+            auth_expiry: Some(60),
+            auth_expiry_txns: Some(2),
+            ..Config::default()
+        });
+        let authorize = |tx, at| Input {
+            timestamp: at,
+            ..auth(InputType::Authorize, tx, Some(dec!(1)))
+        };
+        engine
+            .apply(auth(InputType::Deposit, 1, Some(dec!(10))))
+            .unwrap();
+        engine.apply(authorize(2, Some(100))).unwrap();
+        engine.apply(authorize(3, None)).unwrap();
+        assert_eq!(engine.account(1).unwrap().held, dec!(2));
+
+        // tx 2 runs out of time, tx 3 is still within its two transactions
+        engine.advance_clock(160);
+        let events: Vec<_> = engine.drain_events().collect();
+        assert_eq!(
+            events,
+            vec![Event::AuthExpired(ExpiredAuth {
+                tx: 2,
+                client: 1,
+                currency: None,
+                released: dec!(1),
+                authorized_at: Some(100),
+            })]
+        );
+        assert_eq!(
+            engine.apply(auth(InputType::Capture, 2, None)),
+            Err(TxError::UnknownAuth)
+        );
+        engine
+            .apply(auth(InputType::Deposit, 4, Some(dec!(1))))
+            .unwrap();
+
+        // the third transaction after tx 3 finds it expired
+        assert_eq!(
+            engine.apply(auth(InputType::Capture, 3, None)),
+            Err(TxError::UnknownAuth)
+        );
+        let acc = engine.account(1).unwrap();
+        assert_eq!((acc.available, acc.held), (dec!(11), dec!(0)));
+    }
+
+    fn refund(tx: u32, amount: Option<Decimal>) -> Input {
+        Input::new(InputType::Refund, 1, tx, amount)
+    }
+
+    #[rstest]
+    #[case::full(vec![refund(2, None)], Ok(Applied::Refund(dec!(6))), (dec!(10), dec!(0), dec!(10)))]
+    #[case::partial(vec![refund(2, Some(dec!(2)))], Ok(Applied::Refund(dec!(2))), (dec!(6), dec!(0), dec!(6)))]
+    #[case::cumulative(vec![refund(2, Some(dec!(4))), refund(2, Some(dec!(2)))], Ok(Applied::Refund(dec!(2))), (dec!(10), dec!(0), dec!(10)))]
+    #[case::above_original(vec![refund(2, Some(dec!(4))), refund(2, Some(dec!(3)))], Err(TxError::ExceedsRefundable), (dec!(8), dec!(0), dec!(8)))]
+    #[case::fully_refunded(vec![refund(2, None), refund(2, None)], Err(TxError::ExceedsRefundable), (dec!(10), dec!(0), dec!(10)))]
+    #[case::negative(vec![refund(2, Some(dec!(-1)))], Err(TxError::InvalidAmount), (dec!(4), dec!(0), dec!(4)))]
+    #[case::deposit(vec![refund(1, None)], Err(TxError::NotRefundable), (dec!(4), dec!(0), dec!(4)))]
+    #[case::unknown(vec![refund(3, None)], Err(TxError::UnknownTx), (dec!(4), dec!(0), dec!(4)))]
+    #[case::other_client(vec![Input::new(InputType::Refund, 2, 2, None)], Err(TxError::ClientMismatch), (dec!(4), dec!(0), dec!(4)))]
+    #[case::beside_open_dispute(vec![auth(InputType::Dispute, 2, Some(dec!(4))), refund(2, None)], Ok(Applied::Refund(dec!(2))), (dec!(6), dec!(4), dec!(10)))]
+    #[case::disputed_part(vec![auth(InputType::Dispute, 2, Some(dec!(4))), refund(2, Some(dec!(3)))], Err(TxError::ExceedsRefundable), (dec!(4), dec!(4), dec!(8)))]
+    #[case::after_resolve(vec![auth(InputType::Dispute, 2, None), auth(InputType::Resolve, 2, None), refund(2, None)], Ok(Applied::Refund(dec!(6))), (dec!(10), dec!(0), dec!(10)))]
+    #[case::after_chargeback(vec![auth(InputType::Dispute, 2, Some(dec!(4))), auth(InputType::Chargeback, 2, None), refund(2, None)], Err(TxError::AccountLocked), (dec!(8), dec!(0), dec!(8)))]
+    #[case::refunded_part_cant_be_disputed(vec![refund(2, Some(dec!(4))), auth(InputType::Dispute, 2, Some(dec!(3)))], Err(TxError::ExceedsOriginal), (dec!(8), dec!(0), dec!(8)))]
+    fn test_refunds(
+        #[case] txns: Vec<Input>,
+        #[case] expected_last: Result<Applied, TxError>,
+        #[case] expected: (Decimal, Decimal, Decimal),
+    ) {
+        let mut engine = Engine::with_config(Config {
+            withdrawal_disputes: DisputePolicy::ReversalCredit,
+            ..Config::default()
+        });
+        engine
+            .apply(auth(InputType::Deposit, 1, Some(dec!(10))))
+            .unwrap();
+        engine
+            .apply(auth(InputType::Withdrawal, 2, Some(dec!(6))))
+            .unwrap();
+        let mut results: Vec<_> = txns.into_iter().map(|txn| engine.apply(txn)).collect();
+        assert_eq!(results.pop(), Some(expected_last));
+        assert!(results.iter().all(Result::is_ok));
+
+        let acc = engine.account(1).unwrap();
+        assert_eq!((acc.available, acc.held, acc.total), expected);
+    }
+
+    #[test]
+    fn test_pending_deposits() {
+        let mut holds = FundsAvailability::new();
+        for (from, transactions, seconds) in
+            [(None, Some(2), None), (Some(dec!(100)), None, Some(60))]
+        {
+            holds
+                .insert(HoldRule {
+                    from,
+                    transactions,
+                    seconds,
+                })
+                .unwrap();
+        }
+        let mut engine = Engine::with_config(Config {
+            funds_availability: holds,
+            ..Config::default()
+        });
+        let balances = |engine: &Engine| {
+            let acc = engine.account(1).unwrap();
+            (acc.available, acc.pending, acc.held, acc.total)
+        };
+
+        engine
+            .apply(auth(InputType::Deposit, 1, Some(dec!(10))))
+            .unwrap();
+        assert_eq!(balances(&engine), (dec!(0), dec!(10), dec!(0), dec!(10)));
+        assert_eq!(
+            engine.apply(auth(InputType::Withdrawal, 2, Some(dec!(5)))),
+            Err(TxError::InsufficientFunds)
+        );
+        let large = Input {
+            timestamp: Some(100),
+            ..auth(InputType::Deposit, 3, Some(dec!(500)))
+        };
+        engine.apply(large).unwrap();
+
+        // tx 1 clears once two more transactions went by, tx 3 after a minute
+        engine
+            .apply(auth(InputType::Withdrawal, 4, Some(dec!(5))))
+            .unwrap();
+        assert_eq!(balances(&engine), (dec!(5), dec!(500), dec!(0), dec!(505)));
+        engine.advance_clock(159);
+        assert_eq!(engine.account(1).unwrap().pending, dec!(500));
+        engine.advance_clock(160);
+        assert_eq!(balances(&engine), (dec!(505), dec!(0), dec!(0), dec!(505)));
+
+        // a dispute on a pending deposit holds it out of pending
+        engine
+            .apply(auth(InputType::Deposit, 5, Some(dec!(20))))
+            .unwrap();
+        engine.apply(auth(InputType::Dispute, 5, None)).unwrap();
+        assert_eq!(balances(&engine), (dec!(505), dec!(0), dec!(20), dec!(525)));
+        for tx in 6..9 {
+            let _ = engine.apply(auth(InputType::Withdrawal, tx, Some(dec!(1))));
+        }
+        engine.apply(auth(InputType::Resolve, 5, None)).unwrap();
+        assert_eq!(balances(&engine), (dec!(522), dec!(0), dec!(0), dec!(522)));
+    }
+
+    #[test]
+    fn test_dispute_without_pending_writes_zero() {
+        let mut engine = Engine::new();
+        engine
+            .apply(auth(InputType::Deposit, 1, Some(dec!(10))))
+            .unwrap();
+        engine.apply(auth(InputType::Dispute, 1, None)).unwrap();
+
+        let mut wtr = csv::WriterBuilder::new()
+            .has_headers(false)
+            .from_writer(vec![]);
+        wtr.serialize(engine.account(1).unwrap()).unwrap();
+        let row = String::from_utf8(wtr.into_inner().unwrap()).unwrap();
+        assert_eq!(row, "1,,0,10,0,10,false\n");
+    }
+
+    #[test]
+    fn test_journal() {
+        let mut engine = Engine::with_config(Config {
+            fees: fee_schedule(),
+            journal: true,
+            ..Config::default()
+        });
+        let mut ledger = TrialBalance::new();
+        let client = |account, debit, credit| (Some(1), account, debit, credit);
+        let system = |account, debit, credit| (None, account, debit, credit);
+        let txns = [
+            (
+                auth(InputType::Deposit, 1, Some(dec!(100))),
+                vec![
+                    client(LedgerAccount::Available, dec!(0), dec!(100)),
+                    system(LedgerAccount::Settlement, dec!(100), dec!(0)),
+                ],
+            ),
+            // the fee goes to its own account
+            (
+                auth(InputType::Withdrawal, 2, Some(dec!(10))),
+                vec![
+                    client(LedgerAccount::Available, dec!(11), dec!(0)),
+                    system(LedgerAccount::Fees, dec!(0), dec!(1)),
+                    system(LedgerAccount::Settlement, dec!(0), dec!(10)),
+                ],
+            ),
+            (
+                auth(InputType::Dispute, 1, Some(dec!(50))),
+                vec![
+                    client(LedgerAccount::Available, dec!(50), dec!(0)),
+                    client(LedgerAccount::Held, dec!(0), dec!(50)),
+                ],
+            ),
+            (
+                auth(InputType::Chargeback, 1, None),
+                vec![
+                    client(LedgerAccount::Held, dec!(50), dec!(0)),
+                    client(LedgerAccount::Available, dec!(2), dec!(0)),
+                    system(LedgerAccount::Fees, dec!(0), dec!(2)),
+                    system(LedgerAccount::ChargebackLosses, dec!(0), dec!(50)),
+                ],
+            ),
+        ];
+        for (id, (txn, expected)) in (0..).zip(txns) {
+            engine.apply(txn).unwrap();
+            let entry = engine
+                .drain_events()
+                .find_map(|event| match event {
+                    Event::Journal(entry) => Some(entry),
+                    _ => None,
+                })
+                .unwrap();
+            assert_eq!(entry.id, id);
+            ledger.record(&entry).unwrap();
+            let postings: Vec<_> = entry
+                .postings
+                .into_iter()
+                .map(|p| (p.client, p.account, p.debit, p.credit))
+                .collect();
+            assert_eq!(postings, expected);
+        }
+        // rejected transactions post nothing
+        let _ = engine.apply(auth(InputType::Deposit, 3, Some(dec!(1))));
+        assert!(
+            engine
+                .drain_events()
+                .all(|event| !matches!(event, Event::Journal(_)))
+        );
+        let accounts: Vec<_> = ledger
+            .rows()
+            .map(|row| (row.client, row.account, row.debit, row.credit))
+            .collect();
+        // the held account is back at zero, so it doesn't show
+        assert_eq!(
+            accounts,
+            vec![
+                client(LedgerAccount::Available, dec!(0), dec!(37)),
+                system(LedgerAccount::Settlement, dec!(90), dec!(0)),
+                system(LedgerAccount::ChargebackLosses, dec!(0), dec!(50)),
+                system(LedgerAccount::Fees, dec!(0), dec!(3)),
+            ]
+        );
+        let [check] = ledger
+            .reconcile(engine.accounts())
+            .unwrap()
+            .try_into()
+            .unwrap();
+        assert_eq!(
+            (
+                check.deposits,
+                check.withdrawals,
+                check.chargebacks,
+                check.fees
+            ),
+            (dec!(100), dec!(10), dec!(50), dec!(3))
+        );
+        assert_eq!(check.client_totals, dec!(37));
+        assert!(check.is_reconciled());
+    }
+
+    #[rstest]
+    #[case::deposit_chargeback(DisputePolicy::Hold, vec![auth(InputType::Dispute, 1, Some(dec!(50))), auth(InputType::Chargeback, 1, None)])]
+    #[case::withdrawal_chargeback(DisputePolicy::Hold, vec![auth(InputType::Dispute, 2, None), auth(InputType::Chargeback, 2, None)])]
+    #[case::reversal_resolve(DisputePolicy::ReversalCredit, vec![auth(InputType::Dispute, 2, None), auth(InputType::Resolve, 2, None)])]
+    #[case::reversal_chargeback(DisputePolicy::ReversalCredit, vec![auth(InputType::Dispute, 2, None), auth(InputType::Chargeback, 2, None)])]
+    #[case::reversal_expired(DisputePolicy::ReversalCredit, vec![timed(InputType::Dispute, 2, None, 0), timed(InputType::Deposit, 3, Some(dec!(1)), 100)])]
+    #[case::transfer_chargeback(DisputePolicy::Hold, vec![transfer(3, Some(2), dec!(4)), auth(InputType::Dispute, 3, None), auth(InputType::Chargeback, 3, None)])]
+    #[case::capture_and_void(DisputePolicy::Hold, vec![auth(InputType::Authorize, 3, Some(dec!(20))), auth(InputType::Capture, 3, Some(dec!(5))), auth(InputType::Void, 3, None)])]
+    #[case::refund(DisputePolicy::Hold, vec![refund(2, Some(dec!(4)))])]
+    fn test_journal_entries_reconcile(
+        #[case] withdrawal_disputes: DisputePolicy,
+        #[case] txns: Vec<Input>,
+    ) {
+        let mut engine = Engine::with_config(Config {
+            withdrawal_disputes,
+            fees: fee_schedule(),
+            dispute_timeout: Some(100),
+            journal: true,
+            ..Config::default()
+        });
+        let setup = [
+            auth(InputType::Deposit, 1, Some(dec!(100))),
+            auth(InputType::Withdrawal, 2, Some(dec!(10))),
+        ];
+        // every entry has to balance on its own, without a catch-all leg
+        let mut ledger = TrialBalance::new();
+        for txn in setup.into_iter().chain(txns) {
+            engine.apply(txn).unwrap();
+            for event in engine.drain_events() {
+                if let Event::Journal(entry) = event {
+                    ledger.record(&entry).unwrap();
+                }
+            }
+        }
+        let checks = ledger.reconcile(engine.accounts()).unwrap();
+        assert!(checks.iter().all(Reconciliation::is_reconciled));
+    }
+}
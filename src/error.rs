@@ -0,0 +1,129 @@
+use serde::Serialize;
+use std::fmt;
+
+/// Why the engine refused to apply a transaction.
+///
+/// Serializes as a snake_case code in the rejection log, while `Display`
+/// gives a human readable message.
+#[derive(Debug, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum TxError {
+    /// The account was locked by an earlier chargeback or an admin freeze.
+    AccountLocked,
+    /// The account was closed by an admin.
+    AccountClosed,
+    /// An admin transaction without an `actor`.
+    MissingActor,
+    /// An unlock on an account that isn't locked.
+    NotLocked,
+    /// A withdrawal, transfer or authorization asked for more than the
+    /// available balance plus the client's overdraft limit.
+    InsufficientFunds,
+    /// A deposit, withdrawal, transfer or authorization without an amount.
+    MissingAmount,
+    /// A transfer without a `destination` client.
+    MissingDestination,
+    /// A transfer to the client sending it.
+    InvalidDestination,
+    /// A transfer to a locked, frozen or closed account.
+    DestinationLocked,
+    /// A deposit, withdrawal, transfer or authorization reusing a tx id that
+    /// was already applied.
+    DuplicateTx,
+    /// A dispute, resolve, chargeback or refund referencing a tx we never
+    /// applied.
+    UnknownTx,
+    /// A dispute, resolve, chargeback, refund, capture or void from a client
+    /// that doesn't own the tx.
+    ClientMismatch,
+    /// A dispute on a kind of tx the [`DisputePolicy`](crate::DisputePolicy)
+    /// doesn't allow disputing.
+    DisputeNotAllowed,
+    /// A dispute that would hold more than the client has available, under
+    /// [`ShortfallPolicy::Reject`](crate::ShortfallPolicy::Reject).
+    DisputeExceedsAvailable,
+    /// A dispute filed later than
+    /// [`Config::dispute_window`](crate::Config::dispute_window) after the tx.
+    DisputeWindowClosed,
+    /// A dispute, resolve, chargeback, refund, capture or void naming another
+    /// currency than the tx.
+    CurrencyMismatch,
+    /// A dispute on a resolved tx that was already re-disputed
+    /// [`Config::redispute_limit`](crate::Config::redispute_limit) times.
+    RedisputeLimit,
+    /// A dispute, resolve, chargeback or refund on a tx that was charged back.
+    ChargedBack,
+    /// A dispute on a tx that is already under dispute.
+    AlreadyDisputed,
+    /// A resolve or chargeback on a tx that isn't under dispute.
+    NotDisputed,
+    /// A zero or negative amount on a row whose amount has to be positive.
+    InvalidAmount,
+    /// A dispute for more than the part of the original tx that is neither
+    /// disputed, charged back nor refunded.
+    ExceedsOriginal,
+    /// A refund on a tx other than a withdrawal.
+    NotRefundable,
+    /// A refund for more than the part of the original tx that is neither
+    /// refunded already nor under dispute.
+    ExceedsRefundable,
+    /// A resolve or chargeback for more than is under dispute.
+    ExceedsDisputed,
+    /// A capture or void naming an auth id that isn't open: never authorized,
+    /// or already voided, expired or captured in full.
+    UnknownAuth,
+    /// A capture for more than is left of the authorization.
+    ExceedsAuthorized,
+    /// A risk rule with the `reject` action matched the tx, reported as
+    /// [`Event::RiskHit`](crate::Event::RiskHit) with the rule id.
+    RiskRejected,
+    /// Applying the tx would overflow a balance.
+    Overflow,
+}
+
+impl fmt::Display for TxError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            TxError::AccountLocked => "account is locked",
+            TxError::AccountClosed => "account is closed",
+            TxError::MissingActor => "admin transaction has no actor",
+            TxError::NotLocked => "account is not locked",
+            TxError::InsufficientFunds => "insufficient available funds",
+            TxError::MissingAmount => "transaction has no amount",
+            TxError::MissingDestination => "transfer has no destination",
+            TxError::InvalidDestination => "transfer is to the sending client",
+            TxError::DestinationLocked => "destination account is locked",
+            TxError::DuplicateTx => "tx id was already used",
+            TxError::UnknownTx => "referenced tx does not exist",
+            TxError::ClientMismatch => "referenced tx belongs to another client",
+            TxError::DisputeNotAllowed => "disputes are not allowed for this tx",
+            TxError::DisputeExceedsAvailable => "disputed amount exceeds available funds",
+            TxError::DisputeWindowClosed => "dispute window has closed",
+            TxError::CurrencyMismatch => "currency does not match the referenced tx",
+            TxError::RedisputeLimit => "tx has been re-disputed too many times",
+            TxError::ChargedBack => "tx has been charged back",
+            TxError::AlreadyDisputed => "tx is already disputed",
+            TxError::NotDisputed => "tx is not disputed",
+            TxError::InvalidAmount => "amount must be positive",
+            TxError::ExceedsOriginal => "amount exceeds what is left of the original tx",
+            TxError::ExceedsDisputed => "amount exceeds the disputed amount",
+            TxError::NotRefundable => "only withdrawals can be refunded",
+            TxError::ExceedsRefundable => "amount exceeds what is left to refund",
+            TxError::UnknownAuth => "referenced authorization is not open",
+            TxError::ExceedsAuthorized => "amount exceeds what is left of the authorization",
+            TxError::RiskRejected => "rejected by a risk rule",
+            TxError::Overflow => "balance would overflow",
+        })
+    }
+}
+
+impl std::error::Error for TxError {}
+
+/// A transaction the engine ignored, and why. Serializes as one row of the
+/// rejection log.
+#[derive(Debug, Serialize, Clone, PartialEq, Eq)]
+pub struct Rejection {
+    pub tx: u32,
+    pub client: u16,
+    pub reason: TxError,
+}
@@ -0,0 +1,118 @@
+use crate::{AccountStatus, Currency, InputType, JournalEntry, RiskHit, ShortfallPolicy};
+use rust_decimal::Decimal;
+use serde::Serialize;
+
+/// Something the engine did while applying a transaction that callers may
+/// need to follow up on, beyond the change in balances. Events are buffered
+/// until [`Engine::drain_events`](crate::Engine::drain_events) is called.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Event {
+    Exposure(Exposure),
+    Audit(AuditRecord),
+    DisputeExpired(ExpiredDispute),
+    AuthExpired(ExpiredAuth),
+    Fee(FeeEntry),
+    RiskHit(RiskHit),
+    /// The postings for a balance movement, only reported when
+    /// [`Config::journal`](crate::Config::journal) is set.
+    Journal(JournalEntry),
+    /// A transaction was applied, but a balance was clamped under
+    /// [`OverflowPolicy::Saturate`](crate::OverflowPolicy::Saturate), so part
+    /// of its amount was lost.
+    Saturated {
+        tx: u32,
+        client: u16,
+    },
+    /// A pending deposit, authorization or dispute ran out of time, but
+    /// clearing, voiding or resolving it would overflow a balance under
+    /// [`OverflowPolicy::Reject`](crate::OverflowPolicy::Reject) or `Abort`.
+    /// It was left as it is, and is tried again, and reported again, at every
+    /// later sweep until it goes through.
+    Deferred {
+        tx: u32,
+        client: u16,
+    },
+}
+
+/// A dispute that asked to hold more than the client had available.
+#[derive(Debug, Serialize, Clone, PartialEq, Eq)]
+pub struct Exposure {
+    pub tx: u32,
+    pub client: u16,
+    pub currency: Option<Currency>,
+    /// The amount under dispute.
+    pub disputed: Decimal,
+    /// The part of `disputed` that was not covered by available funds.
+    pub shortfall: Decimal,
+    /// How the shortfall was handled: with `allow-negative` the account went
+    /// negative by `shortfall`, with `cap-hold` only `disputed - shortfall`
+    /// was held.
+    pub policy: ShortfallPolicy,
+}
+
+/// Who changed an account's lock state through an admin transaction, and why.
+#[derive(Debug, Serialize, Clone, PartialEq, Eq)]
+pub struct AuditRecord {
+    pub tx: u32,
+    pub client: u16,
+    pub action: InputType,
+    pub actor: String,
+    pub reason: Option<String>,
+    pub before: AccountStatus,
+    pub after: AccountStatus,
+}
+
+/// A dispute that stayed open past
+/// [`Config::dispute_timeout`](crate::Config::dispute_timeout) and was
+/// resolved automatically.
+#[derive(Debug, Serialize, Clone, PartialEq, Eq)]
+pub struct ExpiredDispute {
+    pub tx: u32,
+    /// The client the held funds were released to, which is the receiver
+    /// for a transfer.
+    pub client: u16,
+    pub currency: Option<Currency>,
+    /// The amount that was under dispute.
+    pub disputed: Decimal,
+    /// The held amount released back to the client.
+    pub released: Decimal,
+    pub opened_at: u64,
+    pub expired_at: u64,
+}
+
+/// An authorization that was neither captured in full nor voided before
+/// [`Config::auth_expiry`](crate::Config::auth_expiry) or
+/// [`Config::auth_expiry_txns`](crate::Config::auth_expiry_txns) ran out,
+/// and was voided automatically.
+#[derive(Debug, Serialize, Clone, PartialEq, Eq)]
+pub struct ExpiredAuth {
+    /// The auth id, which is the tx id of the `authorize` row.
+    pub tx: u32,
+    pub client: u16,
+    pub currency: Option<Currency>,
+    /// The uncaptured amount released back to available.
+    pub released: Decimal,
+    pub authorized_at: Option<u64>,
+}
+
+/// Whether a [`FeeEntry`] takes a fee from the client or gives one back.
+#[derive(Debug, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum FeeKind {
+    Charge,
+    Refund,
+}
+
+/// A fee taken from, or refunded to, a client's available balance, booked
+/// separately from the transaction that caused it.
+#[derive(Debug, Serialize, Clone, PartialEq, Eq)]
+pub struct FeeEntry {
+    /// The transaction the fee belongs to.
+    pub tx: u32,
+    pub client: u16,
+    pub currency: Option<Currency>,
+    /// The type of the transaction that charged or refunded the fee.
+    pub r#type: InputType,
+    pub kind: FeeKind,
+    pub amount: Decimal,
+}
@@ -0,0 +1,172 @@
+use crate::tiers::{Tier, Tiers};
+use crate::{InputType, round_amount};
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fmt;
+
+/// One row of a fee schedule: transactions of `type` with an amount of at
+/// least `from` pay `flat` plus `percent` of the amount, clamped to
+/// `min`..=`max`. Several rows for the same type with different `from`
+/// amounts make up tiers, and the highest tier the amount reaches applies.
+#[derive(Debug, Deserialize, Clone, PartialEq, Eq)]
+pub struct FeeRule {
+    pub r#type: InputType,
+    pub from: Option<Decimal>,
+    pub flat: Option<Decimal>,
+    pub percent: Option<Decimal>,
+    pub min: Option<Decimal>,
+    pub max: Option<Decimal>,
+}
+
+impl FeeRule {
+    fn fee(&self, amount: Decimal) -> Decimal {
+        let percent = self.percent.unwrap_or_default();
+        let fee = round_amount(
+            self.flat
+                .unwrap_or_default()
+                .saturating_add(amount.saturating_mul(percent) / Decimal::ONE_HUNDRED),
+        );
+        let fee = self.min.map_or(fee, |min| fee.max(min));
+        self.max.map_or(fee, |max| fee.min(max))
+    }
+}
+
+impl Tier for FeeRule {
+    fn threshold(&self) -> Decimal {
+        self.from.unwrap_or_default()
+    }
+}
+
+/// A fee rule with a negative value, or a `min` above its `max`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidFee(pub FeeRule);
+
+impl fmt::Display for InvalidFee {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let rule = &self.0;
+        let fields = [
+            ("from", rule.from),
+            ("flat", rule.flat),
+            ("percent", rule.percent),
+            ("min", rule.min),
+            ("max", rule.max),
+        ];
+        let negative = fields
+            .into_iter()
+            .find_map(|(field, value)| Some((field, value?)).filter(|(_, v)| *v < Decimal::ZERO));
+        match (negative, rule.min, rule.max) {
+            (Some((field, value)), _, _) => {
+                write!(f, "fee rule: {field} can't be negative, got {value}")
+            }
+            (None, Some(min), Some(max)) => {
+                write!(f, "fee rule: min {min} is above max {max}")
+            }
+            (None, _, _) => write!(f, "invalid fee rule"),
+        }
+    }
+}
+
+impl std::error::Error for InvalidFee {}
+
+/// The fees charged per transaction type. Types without a rule are free.
+#[derive(Debug, Default, Clone)]
+pub struct FeeSchedule {
+    rules: HashMap<InputType, Tiers<FeeRule>>,
+}
+
+impl FeeSchedule {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a rule, replacing any earlier one for the same type and tier.
+    pub fn insert(&mut self, rule: FeeRule) -> Result<(), InvalidFee> {
+        let values = [rule.from, rule.flat, rule.percent, rule.min, rule.max];
+        let negative = values.into_iter().flatten().any(|v| v < Decimal::ZERO);
+        let inverted = rule.min.zip(rule.max).is_some_and(|(min, max)| min > max);
+        if negative || inverted {
+            return Err(InvalidFee(rule));
+        }
+        self.rules.entry(rule.r#type).or_default().insert(rule);
+        Ok(())
+    }
+
+    /// The fee for a transaction of `r#type` moving `amount`, rounded to 4
+    /// decimal places.
+    pub fn fee(&self, r#type: InputType, amount: Decimal) -> Decimal {
+        self.rules
+            .get(&r#type)
+            .and_then(|tiers| tiers.get(amount))
+            .map_or(Decimal::ZERO, |rule| rule.fee(amount))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+    use rust_decimal_macros::dec;
+
+    fn rule(from: Option<Decimal>, flat: Option<Decimal>, percent: Option<Decimal>) -> FeeRule {
+        FeeRule {
+            r#type: InputType::Withdrawal,
+            from,
+            flat,
+            percent,
+            min: None,
+            max: None,
+        }
+    }
+
+    #[rstest]
+    #[case::flat(vec![rule(None, Some(dec!(0.5)), None)], dec!(100), dec!(0.5))]
+    #[case::percentage(vec![rule(None, None, Some(dec!(1.5)))], dec!(100), dec!(1.5))]
+    #[case::flat_and_percentage(vec![rule(None, Some(dec!(0.5)), Some(dec!(1)))], dec!(100), dec!(1.5))]
+    #[case::rounded(vec![rule(None, None, Some(dec!(1.5)))], dec!(1.2345), dec!(0.0185))]
+    #[case::min(vec![FeeRule { min: Some(dec!(1)), ..rule(None, None, Some(dec!(1))) }], dec!(10), dec!(1))]
+    #[case::max(vec![FeeRule { max: Some(dec!(5)), ..rule(None, None, Some(dec!(1))) }], dec!(1000), dec!(5))]
+    #[case::lower_tier(vec![rule(None, Some(dec!(1)), None), rule(Some(dec!(100)), Some(dec!(2)), None)], dec!(99.9999), dec!(1))]
+    #[case::upper_tier(vec![rule(Some(dec!(100)), Some(dec!(2)), None), rule(None, Some(dec!(1)), None)], dec!(100), dec!(2))]
+    #[case::below_first_tier(vec![rule(Some(dec!(100)), Some(dec!(2)), None)], dec!(50), dec!(0))]
+    #[case::no_rule(vec![], dec!(100), dec!(0))]
+    fn test_fee_schedule(
+        #[case] rules: Vec<FeeRule>,
+        #[case] amount: Decimal,
+        #[case] expected: Decimal,
+    ) {
+        let mut fees = FeeSchedule::new();
+        for rule in rules {
+            fees.insert(rule).unwrap();
+        }
+        assert_eq!(fees.fee(InputType::Withdrawal, amount), expected);
+        assert_eq!(fees.fee(InputType::Deposit, amount), Decimal::ZERO);
+    }
+
+    #[test]
+    fn test_invalid_fee_rules() {
+        let mut fees = FeeSchedule::new();
+        let negative = rule(None, Some(dec!(-1)), None);
+        assert_eq!(
+            fees.insert(negative.clone()),
+            Err(InvalidFee(negative.clone()))
+        );
+        assert_eq!(
+            InvalidFee(negative).to_string(),
+            "fee rule: flat can't be negative, got -1"
+        );
+        let inverted = FeeRule {
+            min: Some(dec!(2)),
+            max: Some(dec!(1)),
+            ..rule(None, None, Some(dec!(1)))
+        };
+        assert_eq!(
+            fees.insert(inverted.clone()),
+            Err(InvalidFee(inverted.clone()))
+        );
+        assert_eq!(
+            InvalidFee(inverted).to_string(),
+            "fee rule: min 2 is above max 1"
+        );
+    }
+}
@@ -0,0 +1,224 @@
+use crate::{Currency, Output, round_amount};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+
+/// One row of an fx rate file: one unit of `from` is worth `rate` units of
+/// `to`, starting at `effective`, or from the beginning of time without one.
+#[derive(Debug, Deserialize, Clone, PartialEq, Eq)]
+pub struct FxRate {
+    pub from: Currency,
+    pub to: Currency,
+    pub rate: Decimal,
+    pub effective: Option<u64>,
+}
+
+/// Why a rate couldn't be loaded or a balance couldn't be converted.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FxError {
+    /// A rate of zero or below.
+    InvalidRate(FxRate),
+    /// No rate from `from` to `to` was in effect at the reporting time.
+    MissingRate { from: Currency, to: Currency },
+    /// A converted balance doesn't fit in a `Decimal`.
+    Overflow { client: u16, currency: Currency },
+}
+
+impl fmt::Display for FxError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FxError::InvalidRate(rate) => write!(
+                f,
+                "rate {} from {} to {} must be positive",
+                rate.rate, rate.from, rate.to
+            ),
+            FxError::MissingRate { from, to } => write!(f, "no rate from {from} to {to}"),
+            FxError::Overflow { client, currency } => write!(
+                f,
+                "converting the {currency} balance of client {client} overflows"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for FxError {}
+
+/// Exchange rates by currency pair, each with its history of effective times.
+///
+/// Only direct pairs are used: a rate from `EUR` to `USD` doesn't imply one
+/// from `USD` to `EUR`, since inverting it would round.
+#[derive(Debug, Default, Clone)]
+pub struct RateTable {
+    rates: HashMap<(Currency, Currency), BTreeMap<u64, Decimal>>,
+}
+
+impl RateTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a rate. A later rate for the same pair and effective time
+    /// replaces the earlier one.
+    pub fn insert(&mut self, rate: FxRate) -> Result<(), FxError> {
+        if rate.rate <= Decimal::ZERO {
+            return Err(FxError::InvalidRate(rate));
+        }
+        self.rates
+            .entry((rate.from, rate.to))
+            .or_default()
+            .insert(rate.effective.unwrap_or(0), rate.rate);
+        Ok(())
+    }
+
+    /// Returns the rate from `from` to `to` in effect at `at`, or the latest
+    /// one if `at` is `None`. A currency always converts to itself at 1.
+    pub fn rate(&self, from: Currency, to: Currency, at: Option<u64>) -> Option<Decimal> {
+        if from == to {
+            return Some(Decimal::ONE);
+        }
+        let history = self.rates.get(&(from, to))?;
+        let (_, rate) = history.range(..=at.unwrap_or(u64::MAX)).next_back()?;
+        Some(*rate)
+    }
+}
+
+/// A client's balances across all currencies, converted to one base
+/// currency.
+#[derive(Debug, Serialize, Clone, PartialEq, Eq)]
+pub struct Consolidated {
+    pub client: u16,
+    pub currency: Currency,
+    /// The sum of each balance's total converted and rounded to 4 decimal
+    /// places.
+    pub total: Decimal,
+    /// What rounding took off, so that `total + residual` is the exact
+    /// converted amount.
+    pub residual: Decimal,
+}
+
+/// Converts every account to `base` with the rates in effect at `at`,
+/// returning one row per client, ordered by client id. Accounts without a
+/// currency are taken to be in `base` already.
+pub(crate) fn consolidate<'a>(
+    accounts: impl Iterator<Item = &'a Output>,
+    rates: &RateTable,
+    base: Currency,
+    at: Option<u64>,
+) -> Result<Vec<Consolidated>, FxError> {
+    let mut clients: BTreeMap<u16, Consolidated> = BTreeMap::new();
+    for acc in accounts {
+        let currency = acc.currency.unwrap_or(base);
+        let rate = rates.rate(currency, base, at).ok_or(FxError::MissingRate {
+            from: currency,
+            to: base,
+        })?;
+        let overflow = || FxError::Overflow {
+            client: acc.client,
+            currency,
+        };
+        let exact = acc.total.checked_mul(rate).ok_or_else(overflow)?;
+        let rounded = round_amount(exact);
+        let row = clients.entry(acc.client).or_insert(Consolidated {
+            client: acc.client,
+            currency: base,
+            total: Decimal::ZERO,
+            residual: Decimal::ZERO,
+        });
+        row.total = row.total.checked_add(rounded).ok_or_else(overflow)?;
+        row.residual = row
+            .residual
+            .checked_add(exact - rounded)
+            .ok_or_else(overflow)?;
+    }
+    Ok(clients.into_values().collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Engine, Input, InputType};
+    use rust_decimal_macros::dec;
+
+    fn currency(code: &str) -> Currency {
+        code.parse().unwrap()
+    }
+
+    fn rate(from: &str, to: &str, rate: Decimal, effective: Option<u64>) -> FxRate {
+        FxRate {
+            from: currency(from),
+            to: currency(to),
+            rate,
+            effective,
+        }
+    }
+
+    #[test]
+    fn test_rate_in_effect() {
+        let mut rates = RateTable::new();
+        rates.insert(rate("EUR", "USD", dec!(1.1), None)).unwrap();
+        rates
+            .insert(rate("EUR", "USD", dec!(1.2), Some(100)))
+            .unwrap();
+        let (eur, usd) = (currency("EUR"), currency("USD"));
+
+        assert_eq!(rates.rate(eur, usd, Some(99)), Some(dec!(1.1)));
+        assert_eq!(rates.rate(eur, usd, Some(100)), Some(dec!(1.2)));
+        assert_eq!(rates.rate(eur, usd, None), Some(dec!(1.2)));
+        assert_eq!(rates.rate(usd, eur, None), None);
+        assert_eq!(rates.rate(usd, usd, None), Some(Decimal::ONE));
+        assert_eq!(
+            rates.insert(rate("EUR", "USD", dec!(0), None)),
+            Err(FxError::InvalidRate(rate("EUR", "USD", dec!(0), None)))
+        );
+    }
+
+    #[test]
+    fn test_consolidated_totals_reconcile() {
+        let mut engine = Engine::new();
+        for (client, tx, amount, code) in [
+            (1, 1, dec!(10.0001), Some("EUR")),
+            (1, 2, dec!(3.3333), Some("GBP")),
+            (1, 3, dec!(2), None),
+            (2, 4, dec!(1), Some("EUR")),
+        ] {
+            let deposit = Input {
+                currency: code.map(currency),
+                ..Input::new(InputType::Deposit, client, tx, Some(amount))
+            };
+            engine.apply(deposit).unwrap();
+        }
+        let mut rates = RateTable::new();
+        rates
+            .insert(rate("EUR", "USD", dec!(1.08765), None))
+            .unwrap();
+        rates.insert(rate("GBP", "USD", dec!(1.27), None)).unwrap();
+
+        let report = engine.consolidated(&rates, currency("USD")).unwrap();
+        assert_eq!(
+            report,
+            vec![
+                Consolidated {
+                    client: 1,
+                    currency: currency("USD"),
+                    // 10.876608765 + 4.233291 + 2, each rounded
+                    total: dec!(17.1099),
+                    residual: dec!(-0.000000235),
+                },
+                Consolidated {
+                    client: 2,
+                    currency: currency("USD"),
+                    total: dec!(1.0876),
+                    residual: dec!(0.00005),
+                },
+            ]
+        );
+        let exact = dec!(10.0001) * dec!(1.08765) + dec!(3.3333) * dec!(1.27) + dec!(2);
+        assert_eq!(report[0].total + report[0].residual, exact);
+
+        assert!(matches!(
+            engine.consolidated(&rates, currency("JPY")),
+            Err(FxError::MissingRate { .. })
+        ));
+    }
+}
@@ -0,0 +1,499 @@
+use crate::{Currency, InputType, Output};
+use rust_decimal::Decimal;
+use serde::{Serialize, Serializer};
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// A ledger account postings are made against. Client balances are the
+/// system's liabilities to its clients, so money reaching a client is a
+/// credit to one of their accounts and a debit to where it came from.
+#[derive(Debug, Serialize, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum LedgerAccount {
+    /// A client's available balance.
+    Available,
+    /// A client's held balance.
+    Held,
+    /// A client's deposits that haven't cleared yet.
+    Pending,
+    /// Money coming in through deposits and refunds, and going out through
+    /// withdrawals and captures.
+    Settlement,
+    /// The other side of chargebacks and reversal credits: debited for what
+    /// they give back to clients, credited for what they take away.
+    ChargebackLosses,
+    /// Fees charged to clients, net of refunds.
+    Fees,
+}
+
+impl LedgerAccount {
+    /// Whether the account belongs to a client rather than the system.
+    pub fn is_client(self) -> bool {
+        matches!(
+            self,
+            LedgerAccount::Available | LedgerAccount::Held | LedgerAccount::Pending
+        )
+    }
+}
+
+/// What caused a journal entry: a transaction, or something the engine did
+/// on its own when a deadline passed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Movement {
+    Tx(InputType),
+    DisputeExpired,
+    AuthExpired,
+    DepositCleared,
+}
+
+impl Serialize for Movement {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Movement::Tx(r#type) => r#type.serialize(serializer),
+            Movement::DisputeExpired => serializer.serialize_str("dispute_expired"),
+            Movement::AuthExpired => serializer.serialize_str("auth_expired"),
+            Movement::DepositCleared => serializer.serialize_str("deposit_cleared"),
+        }
+    }
+}
+
+/// One line of a journal entry. Exactly one of `debit` and `credit` is
+/// non-zero.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Posting {
+    /// The client owning `account`, for client accounts.
+    pub client: Option<u16>,
+    pub account: LedgerAccount,
+    pub debit: Decimal,
+    pub credit: Decimal,
+}
+
+/// The postings for one balance movement, in one currency.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JournalEntry {
+    /// Numbers entries in the order they were made, starting at 0.
+    pub id: u64,
+    pub tx: u32,
+    pub movement: Movement,
+    pub currency: Option<Currency>,
+    pub postings: Vec<Posting>,
+}
+
+/// A [`Posting`] with the entry it belongs to, as one row of the journal
+/// csv.
+#[derive(Debug, Serialize, Clone, PartialEq, Eq)]
+pub struct JournalRow {
+    pub entry: u64,
+    pub tx: u32,
+    pub movement: Movement,
+    pub currency: Option<Currency>,
+    pub client: Option<u16>,
+    pub account: LedgerAccount,
+    pub debit: Decimal,
+    pub credit: Decimal,
+}
+
+impl JournalEntry {
+    pub(crate) fn new(tx: u32, movement: Movement, currency: Option<Currency>) -> Self {
+        Self {
+            id: 0,
+            tx,
+            movement,
+            currency,
+            postings: Vec::new(),
+        }
+    }
+
+    /// Checks that the debits add up to the credits.
+    pub fn check(&self) -> Result<(), JournalError> {
+        let overflow = JournalError::Overflow {
+            currency: self.currency,
+        };
+        let (mut debits, mut credits) = (Decimal::ZERO, Decimal::ZERO);
+        for posting in &self.postings {
+            debits = debits.checked_add(posting.debit).ok_or(overflow)?;
+            credits = credits.checked_add(posting.credit).ok_or(overflow)?;
+        }
+        if debits != credits {
+            return Err(JournalError::Unbalanced {
+                entry: self.id,
+                tx: self.tx,
+            });
+        }
+        Ok(())
+    }
+
+    /// The entry as journal csv rows, one per posting.
+    pub fn rows(&self) -> impl Iterator<Item = JournalRow> + '_ {
+        self.postings.iter().map(|posting| JournalRow {
+            entry: self.id,
+            tx: self.tx,
+            movement: self.movement,
+            currency: self.currency,
+            client: posting.client,
+            account: posting.account,
+            debit: posting.debit,
+            credit: posting.credit,
+        })
+    }
+
+    /// Posts `delta` to a client account: an increase is a credit.
+    pub(crate) fn client(mut self, client: u16, account: LedgerAccount, delta: Decimal) -> Self {
+        self.post(Some(client), account, -delta);
+        self
+    }
+
+    /// Posts `flow` reaching the clients from a system account: money paid
+    /// in is a debit to where it came from.
+    pub(crate) fn external(mut self, account: LedgerAccount, flow: Decimal) -> Self {
+        self.post(None, account, flow);
+        self
+    }
+
+    /// Adds `amount` to the account's posting as a debit, or as a credit when
+    /// it is negative, so each account appears at most once and never with a
+    /// zero amount.
+    fn post(&mut self, client: Option<u16>, account: LedgerAccount, amount: Decimal) {
+        if amount.is_zero() {
+            return;
+        }
+        let index = self
+            .postings
+            .iter()
+            .position(|posting| posting.client == client && posting.account == account);
+        let Some(index) = index else {
+            let (debit, credit) = sides(amount);
+            self.postings.push(Posting {
+                client,
+                account,
+                debit,
+                credit,
+            });
+            return;
+        };
+        let posting = &mut self.postings[index];
+        let net = posting.debit - posting.credit + amount;
+        if net.is_zero() {
+            self.postings.remove(index);
+        } else {
+            (posting.debit, posting.credit) = sides(net);
+        }
+    }
+}
+
+/// Splits a balance of debits minus credits into its debit and credit side.
+fn sides(balance: Decimal) -> (Decimal, Decimal) {
+    if balance > Decimal::ZERO {
+        (balance, Decimal::ZERO)
+    } else if balance < Decimal::ZERO {
+        (Decimal::ZERO, -balance)
+    } else {
+        (Decimal::ZERO, Decimal::ZERO)
+    }
+}
+
+/// Why a journal entry, or a trial balance, doesn't add up.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JournalError {
+    /// An entry whose debits don't equal its credits.
+    Unbalanced { entry: u64, tx: u32 },
+    /// A sum of postings, or of balances, in the currency is more than a
+    /// decimal can hold.
+    Overflow { currency: Option<Currency> },
+}
+
+impl fmt::Display for JournalError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            JournalError::Unbalanced { entry, tx } => {
+                write!(f, "journal entry {entry} for tx {tx} doesn't balance")
+            }
+            JournalError::Overflow { currency: None } => {
+                f.write_str("ledger balances without a currency overflow")
+            }
+            JournalError::Overflow {
+                currency: Some(currency),
+            } => write!(f, "ledger balances in {currency} overflow"),
+        }
+    }
+}
+
+impl std::error::Error for JournalError {}
+
+/// Every ledger account's balance, summed from the journal entries recorded
+/// so far.
+#[derive(Debug, Default, Clone)]
+pub struct TrialBalance {
+    /// Debits minus credits, by currency with each client's accounts before
+    /// the system's.
+    balances: BTreeMap<(Option<Currency>, bool, Option<u16>, LedgerAccount), Decimal>,
+    /// The settlement postings of each currency, split up by the type of
+    /// transaction that made them.
+    flows: BTreeMap<Option<Currency>, Reconciliation>,
+}
+
+/// One account of a [`TrialBalance`], with its balance on the side it
+/// falls on.
+#[derive(Debug, Serialize, Clone, PartialEq, Eq)]
+pub struct TrialBalanceRow {
+    pub currency: Option<Currency>,
+    pub account: LedgerAccount,
+    pub client: Option<u16>,
+    pub debit: Decimal,
+    pub credit: Decimal,
+}
+
+/// How a currency's ledger accounts add up against the clients' totals.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Reconciliation {
+    pub currency: Option<Currency>,
+    /// Paid in through deposits.
+    pub deposits: Decimal,
+    /// Paid back to clients through refunds of withdrawals.
+    pub refunds: Decimal,
+    /// Paid out through withdrawals.
+    pub withdrawals: Decimal,
+    /// Paid out through captured authorizations.
+    pub captures: Decimal,
+    /// What chargebacks and reversal credits took from clients, net of what
+    /// they gave back.
+    pub chargebacks: Decimal,
+    /// Fees charged, net of refunds.
+    pub fees: Decimal,
+    /// The sum of the clients' `total` balances.
+    pub client_totals: Decimal,
+}
+
+impl Reconciliation {
+    fn new(currency: Option<Currency>) -> Self {
+        Self {
+            currency,
+            deposits: Decimal::ZERO,
+            refunds: Decimal::ZERO,
+            withdrawals: Decimal::ZERO,
+            captures: Decimal::ZERO,
+            chargebacks: Decimal::ZERO,
+            fees: Decimal::ZERO,
+            client_totals: Decimal::ZERO,
+        }
+    }
+
+    /// What the clients should hold in total going by the system's
+    /// accounts: deposits and refunds, less withdrawals, captures,
+    /// chargebacks and fees. `None` if that is more than a decimal can hold.
+    pub fn expected_totals(&self) -> Option<Decimal> {
+        self.deposits
+            .checked_add(self.refunds)?
+            .checked_sub(self.withdrawals)?
+            .checked_sub(self.captures)?
+            .checked_sub(self.chargebacks)?
+            .checked_sub(self.fees)
+    }
+
+    /// Whether the clients' balances hold what the system's accounts say
+    /// they should.
+    pub fn is_reconciled(&self) -> bool {
+        self.expected_totals() == Some(self.client_totals)
+    }
+}
+
+impl TrialBalance {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Checks an entry and adds its postings to the balances. Nothing is
+    /// added if it doesn't balance or a balance would overflow.
+    pub fn record(&mut self, entry: &JournalEntry) -> Result<(), JournalError> {
+        entry.check()?;
+        let overflow = JournalError::Overflow {
+            currency: entry.currency,
+        };
+        let mut flows = self
+            .flows
+            .get(&entry.currency)
+            .cloned()
+            .unwrap_or_else(|| Reconciliation::new(entry.currency));
+        let mut balances = Vec::with_capacity(entry.postings.len());
+        for posting in &entry.postings {
+            let key = (
+                entry.currency,
+                posting.client.is_none(),
+                posting.client,
+                posting.account,
+            );
+            // only one side of a posting is ever set
+            let net = posting.debit - posting.credit;
+            let balance = self.balances.get(&key).copied().unwrap_or_default();
+            balances.push((key, balance.checked_add(net).ok_or(overflow)?));
+            if posting.account != LedgerAccount::Settlement {
+                continue;
+            }
+            let (column, amount) = match entry.movement {
+                Movement::Tx(InputType::Deposit) => (&mut flows.deposits, net),
+                Movement::Tx(InputType::Refund) => (&mut flows.refunds, net),
+                Movement::Tx(InputType::Withdrawal) => (&mut flows.withdrawals, -net),
+                Movement::Tx(InputType::Capture) => (&mut flows.captures, -net),
+                // nothing else settles, and if it did it would show up as a
+                // mismatch in the reconciliation
+                _ => continue,
+            };
+            *column = column.checked_add(amount).ok_or(overflow)?;
+        }
+        self.balances.extend(balances);
+        self.flows.insert(entry.currency, flows);
+        Ok(())
+    }
+
+    /// Every account with a balance, by currency, clients first.
+    pub fn rows(&self) -> impl Iterator<Item = TrialBalanceRow> + '_ {
+        self.balances
+            .iter()
+            .filter(|(_, balance)| !balance.is_zero())
+            .map(|(&(currency, _, client, account), &balance)| {
+                let (debit, credit) = sides(balance);
+                TrialBalanceRow {
+                    currency,
+                    account,
+                    client,
+                    debit,
+                    credit,
+                }
+            })
+    }
+
+    /// Checks the balances of each currency against the clients' `accounts`,
+    /// returning one row per currency in either.
+    pub fn reconcile<'a>(
+        &self,
+        accounts: impl Iterator<Item = &'a Output>,
+    ) -> Result<Vec<Reconciliation>, JournalError> {
+        let mut currencies = self.flows.clone();
+        for (&(currency, _, _, account), &balance) in &self.balances {
+            let row = currencies
+                .entry(currency)
+                .or_insert_with(|| Reconciliation::new(currency));
+            // one balance per account, so these can't overflow
+            match account {
+                LedgerAccount::ChargebackLosses => row.chargebacks = -balance,
+                LedgerAccount::Fees => row.fees = -balance,
+                LedgerAccount::Available
+                | LedgerAccount::Held
+                | LedgerAccount::Pending
+                | LedgerAccount::Settlement => {}
+            }
+        }
+        for acc in accounts {
+            let row = currencies
+                .entry(acc.currency)
+                .or_insert_with(|| Reconciliation::new(acc.currency));
+            row.client_totals =
+                row.client_totals
+                    .checked_add(acc.total)
+                    .ok_or(JournalError::Overflow {
+                        currency: acc.currency,
+                    })?;
+        }
+        Ok(currencies.into_values().collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn test_postings_net_out_per_account() {
+        let entry = JournalEntry::new(1, Movement::Tx(InputType::Withdrawal), None)
+            .client(1, LedgerAccount::Available, dec!(-10))
+            .client(1, LedgerAccount::Available, dec!(-1))
+            .client(1, LedgerAccount::Held, dec!(5))
+            .client(1, LedgerAccount::Held, dec!(-5))
+            .external(LedgerAccount::Fees, dec!(-1))
+            .external(LedgerAccount::Settlement, dec!(-10));
+        let postings: Vec<_> = entry
+            .postings
+            .iter()
+            .map(|p| (p.client, p.account, p.debit, p.credit))
+            .collect();
+        assert_eq!(
+            postings,
+            vec![
+                (Some(1), LedgerAccount::Available, dec!(11), dec!(0)),
+                (None, LedgerAccount::Fees, dec!(0), dec!(1)),
+                (None, LedgerAccount::Settlement, dec!(0), dec!(10)),
+            ]
+        );
+        assert_eq!(entry.check(), Ok(()));
+
+        // a missing leg shows up as an unbalanced entry
+        let entry = JournalEntry::new(2, Movement::Tx(InputType::Deposit), None).client(
+            1,
+            LedgerAccount::Available,
+            dec!(10),
+        );
+        assert_eq!(
+            entry.check(),
+            Err(JournalError::Unbalanced { entry: 0, tx: 2 })
+        );
+    }
+
+    #[test]
+    fn test_record_overflow() {
+        let deposit = |client| {
+            JournalEntry::new(1, Movement::Tx(InputType::Deposit), None)
+                .client(client, LedgerAccount::Available, dec!(5e28))
+                .external(LedgerAccount::Settlement, dec!(5e28))
+        };
+        let mut ledger = TrialBalance::new();
+        ledger.record(&deposit(1)).unwrap();
+        // the settlement account would take both deposits
+        assert_eq!(
+            ledger.record(&deposit(2)),
+            Err(JournalError::Overflow { currency: None })
+        );
+        assert_eq!(ledger.rows().count(), 2);
+    }
+
+    #[test]
+    fn test_reconcile_splits_settlement() {
+        let mut ledger = TrialBalance::new();
+        for (r#type, paid_in) in [
+            (InputType::Deposit, dec!(100)),
+            (InputType::Withdrawal, dec!(-10)),
+            (InputType::Capture, dec!(-5)),
+            (InputType::Refund, dec!(4)),
+        ] {
+            let entry = JournalEntry::new(1, Movement::Tx(r#type), None)
+                .client(1, LedgerAccount::Available, paid_in)
+                .external(LedgerAccount::Settlement, paid_in);
+            ledger.record(&entry).unwrap();
+        }
+        let mut account = Output {
+            client: 1,
+            total: dec!(89),
+            ..Output::default()
+        };
+        let [check] = ledger
+            .reconcile([&account].into_iter())
+            .unwrap()
+            .try_into()
+            .unwrap();
+        assert_eq!(
+            (
+                check.deposits,
+                check.refunds,
+                check.withdrawals,
+                check.captures
+            ),
+            (dec!(100), dec!(4), dec!(10), dec!(5))
+        );
+        assert!(check.is_reconciled());
+
+        // a balance that moved outside the journal doesn't reconcile
+        account.total = dec!(90);
+        let checks = ledger.reconcile([&account].into_iter()).unwrap();
+        assert!(!checks[0].is_reconciled());
+    }
+}
@@ -0,0 +1,550 @@
+//! Core of the csv transaction simulator.
+//!
+//! The [`Engine`] applies client transactions (deposits, withdrawals and the
+//! dispute lifecycle) to an in-memory set of accounts. [`process_transactions`]
+//! is a convenience wrapper for running a whole batch at once. Every ignored
+//! transaction is reported as a [`TxError`].
+
+mod availability;
+mod config;
+mod currency;
+mod engine;
+mod error;
+mod event;
+mod fee;
+mod fx;
+mod journal;
+mod limits;
+mod reader;
+mod risk;
+mod rules;
+mod tiers;
+
+pub use availability::{FundsAvailability, HoldRule, InvalidHoldRule};
+pub use config::{
+    Config, DisputePolicy, DuplicatePolicy, FeeRefundPolicy, OverflowPolicy, ShortfallPolicy,
+};
+pub use currency::{Currency, InvalidCurrency};
+pub use engine::{Applied, Engine, Summary, TxState};
+pub use error::{Rejection, TxError};
+pub use event::{AuditRecord, Event, ExpiredAuth, ExpiredDispute, Exposure, FeeEntry, FeeKind};
+pub use fee::{FeeRule, FeeSchedule, InvalidFee};
+pub use fx::{Consolidated, FxError, FxRate, RateTable};
+pub use journal::{
+    JournalEntry, JournalError, JournalRow, LedgerAccount, Movement, Posting, Reconciliation,
+    TrialBalance, TrialBalanceRow,
+};
+pub use limits::{InvalidLimit, NegativeBalance, OverdraftLimit, OverdraftLimits};
+pub use reader::{InputReader, ParseError};
+pub use risk::{InvalidRiskRule, RiskAction, RiskCheck, RiskHit, RiskRule, RiskRules};
+pub use rules::{RuleError, ScriptRule, parse_rules};
+
+use rust_decimal::{Decimal, RoundingStrategy};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Decimal places of input amounts, which fees and converted balances are
+/// rounded to.
+const AMOUNT_DP: u32 = 4;
+
+/// Rounds a computed amount to [`AMOUNT_DP`] places, half to even.
+pub(crate) fn round_amount(amount: Decimal) -> Decimal {
+    amount.round_dp_with_strategy(AMOUNT_DP, RoundingStrategy::MidpointNearestEven)
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum InputType {
+    Deposit,
+    Withdrawal,
+    Dispute,
+    Resolve,
+    Chargeback,
+    /// Move funds from `client` to `destination`.
+    Transfer,
+    /// Hold `amount` for a later capture, under the row's tx id as auth id.
+    Authorize,
+    /// Settle all or part of an open authorization.
+    Capture,
+    /// Release what is left of an open authorization.
+    Void,
+    /// Give back all or part of the withdrawal `tx`.
+    Refund,
+    /// Admin: lift a chargeback lock or a freeze.
+    Unlock,
+    /// Admin: lock the account until it is unlocked.
+    Freeze,
+    /// Admin: permanently close the account.
+    Close,
+}
+
+impl InputType {
+    /// Whether this is an administrative action, which must name an `actor`
+    /// and is allowed on locked accounts.
+    pub fn is_admin(self) -> bool {
+        matches!(
+            self,
+            InputType::Unlock | InputType::Freeze | InputType::Close
+        )
+    }
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct Input {
+    pub r#type: InputType,
+    pub client: u16,
+    pub tx: u32,
+    // Decimal is prefered for financial data because:
+    // 1. It avoids floating point errors
+    // 2. maintains the exact decimal representation.
+    // Alternative would be to use integers and track the decimal place/precision separately.
+    pub amount: Option<Decimal>,
+    /// The receiving client of a transfer.
+    pub destination: Option<u16>,
+    /// The currency of `amount`. Balances are kept separately per currency;
+    /// rows without one share a single unnamed balance.
+    pub currency: Option<Currency>,
+    /// Who issued an admin transaction. Required for admin types, ignored for
+    /// customer traffic.
+    pub actor: Option<String>,
+    /// Why an admin transaction was issued, kept in the audit record.
+    pub reason: Option<String>,
+    /// When the transaction happened, as seconds since the unix epoch. Drives
+    /// the dispute windows in [`Config`].
+    pub timestamp: Option<u64>,
+}
+
+impl Input {
+    /// A transaction with none of the optional columns set.
+    pub fn new(r#type: InputType, client: u16, tx: u32, amount: Option<Decimal>) -> Self {
+        Self {
+            r#type,
+            client,
+            tx,
+            amount,
+            destination: None,
+            currency: None,
+            actor: None,
+            reason: None,
+            timestamp: None,
+        }
+    }
+}
+
+/// Lifecycle of a client account. Anything but `Active` shows up as
+/// `locked` in the csv output. The status applies to the client as a whole,
+/// across all of their currencies.
+#[derive(Debug, Serialize, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum AccountStatus {
+    #[default]
+    Active,
+    /// Locked by a chargeback.
+    Locked,
+    /// Locked by an admin freeze.
+    Frozen,
+    /// Closed by an admin. Final: a closed account can't be unlocked.
+    Closed,
+}
+
+#[derive(Debug, Serialize, Default, Clone)]
+pub struct Output {
+    pub client: u16,
+    pub currency: Option<Currency>,
+    pub available: Decimal,
+    pub held: Decimal,
+    /// Deposits that haven't cleared yet under the
+    /// [`FundsAvailability`] policy. Part of `total`, but can't be spent.
+    pub pending: Decimal,
+    pub total: Decimal,
+    pub locked: bool,
+    #[serde(skip)]
+    pub status: AccountStatus,
+    /// Set when the account needs a follow-up from risk, e.g. it was allowed
+    /// to go negative by a dispute or hit a flagging [`RiskRule`]. Not part
+    /// of the csv output.
+    #[serde(skip)]
+    pub flagged: bool,
+}
+
+impl Output {
+    pub(crate) fn set_status(&mut self, status: AccountStatus) {
+        self.status = status;
+        self.locked = status != AccountStatus::Active;
+    }
+}
+
+/// Runs every transaction through a fresh [`Engine`] and returns the
+/// resulting accounts keyed by client id and currency. Rejections and events
+/// are dropped; drive an [`Engine`] directly to see them.
+pub fn process_transactions(
+    transactions: impl Iterator<Item = Input>,
+) -> HashMap<(u16, Option<Currency>), Output> {
+    let mut engine = Engine::new();
+    for txn in transactions {
+        let _ = engine.apply(txn);
+        engine.drain_events().for_each(drop);
+    }
+    engine.into_accounts()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+    use rust_decimal_macros::dec;
+
+    #[rstest]
+    #[case::deposit(vec![(InputType::Deposit, 1, 1, Some(dec!(10)))], 1, dec!(10), dec!(0), dec!(10), false, Ok(Applied::Deposit(dec!(10))))]
+    #[case::withdrawal_success(vec![(InputType::Deposit, 1, 1, Some(dec!(10))), (InputType::Withdrawal, 1, 2, Some(dec!(5)))], 1, dec!(5), dec!(0), dec!(5), false, Ok(Applied::Withdrawal(dec!(5))))]
+    #[case::withdrawal_insufficient(vec![(InputType::Deposit, 1, 1, Some(dec!(10))), (InputType::Withdrawal, 1, 2, Some(dec!(15)))], 1, dec!(10), dec!(0), dec!(10), false, Err(TxError::InsufficientFunds))]
+    #[case::dispute(vec![(InputType::Deposit, 1, 1, Some(dec!(10))), (InputType::Dispute, 1, 1, None)], 1, dec!(0), dec!(10), dec!(10), false, Ok(Applied::Dispute(dec!(10))))]
+    #[case::resolve(vec![(InputType::Deposit, 1, 1, Some(dec!(10))), (InputType::Dispute, 1, 1, None), (InputType::Resolve, 1, 1, None)], 1, dec!(10), dec!(0), dec!(10), false, Ok(Applied::Resolve(dec!(10))))]
+    #[case::chargeback(vec![(InputType::Deposit, 1, 1, Some(dec!(10))), (InputType::Dispute, 1, 1, None), (InputType::Chargeback, 1, 1, None)], 1, dec!(0), dec!(0), dec!(0), true, Ok(Applied::Chargeback(dec!(10))))]
+    #[case::chargeback_deposit(vec![(InputType::Deposit, 1, 1, Some(dec!(10))), (InputType::Deposit, 1, 2, Some(dec!(5))), (InputType::Dispute, 1, 2, None), (InputType::Chargeback, 1, 2, None)], 1, dec!(10), dec!(0), dec!(10), true, Ok(Applied::Chargeback(dec!(5))))]
+    #[case::chargeback_withdrawal(vec![(InputType::Deposit, 1, 1, Some(dec!(10))), (InputType::Withdrawal, 1, 2, Some(dec!(4))), (InputType::Dispute, 1, 2, None), (InputType::Chargeback, 1, 2, None)], 1, dec!(10), dec!(0), dec!(10), true, Ok(Applied::Chargeback(dec!(4))))]
+    #[case::locked_ignores_txns(vec![(InputType::Deposit, 1, 1, Some(dec!(10))), (InputType::Dispute, 1, 1, None), (InputType::Chargeback, 1, 1, None), (InputType::Deposit, 1, 2, Some(dec!(5)))], 1, dec!(0), dec!(0), dec!(0), true, Err(TxError::AccountLocked))]
+    #[case::dispute_nonexistent(vec![(InputType::Deposit, 1, 1, Some(dec!(10))), (InputType::Dispute, 1, 999, None)], 1, dec!(10), dec!(0), dec!(10), false, Err(TxError::UnknownTx))]
+    #[case::double_dispute(vec![(InputType::Deposit, 1, 1, Some(dec!(10))), (InputType::Dispute, 1, 1, None), (InputType::Dispute, 1, 1, None)], 1, dec!(0), dec!(10), dec!(10), false, Err(TxError::AlreadyDisputed))]
+    #[case::resolve_non_disputed(vec![(InputType::Deposit, 1, 1, Some(dec!(10))), (InputType::Resolve, 1, 1, None)], 1, dec!(10), dec!(0), dec!(10), false, Err(TxError::NotDisputed))]
+    #[case::chargeback_non_disputed(vec![(InputType::Deposit, 1, 1, Some(dec!(10))), (InputType::Chargeback, 1, 1, None)], 1, dec!(10), dec!(0), dec!(10), false, Err(TxError::NotDisputed))]
+    #[case::dispute_exceeds_available(vec![(InputType::Deposit, 1, 1, Some(dec!(10))), (InputType::Withdrawal, 1, 2, Some(dec!(8))), (InputType::Dispute, 1, 1, None)], 1, dec!(-8), dec!(10), dec!(2), false, Ok(Applied::Dispute(dec!(10))))]
+    #[case::dispute_withdrawal(vec![(InputType::Deposit, 1, 1, Some(dec!(10))), (InputType::Withdrawal, 1, 2, Some(dec!(5))), (InputType::Dispute, 1, 2, None)], 1, dec!(0), dec!(5), dec!(5), false, Ok(Applied::Dispute(dec!(5))))]
+    #[case::multiple_clients(vec![(InputType::Deposit, 1, 1, Some(dec!(10))), (InputType::Deposit, 2, 2, Some(dec!(20))), (InputType::Withdrawal, 1, 3, Some(dec!(5)))], 1, dec!(5), dec!(0), dec!(5), false, Ok(Applied::Withdrawal(dec!(5))))]
+    #[case::saturation(vec![(InputType::Deposit, 1, 1, Some(Decimal::MAX)), (InputType::Deposit, 1, 2, Some(dec!(1)))], 1, Decimal::MAX, dec!(0), Decimal::MAX, false, Ok(Applied::Deposit(dec!(1))))]
+    #[case::cross_client_dispute(vec![(InputType::Deposit, 1, 1, Some(dec!(10))), (InputType::Dispute, 2, 1, None)], 1, dec!(10), dec!(0), dec!(10), false, Err(TxError::ClientMismatch))]
+    #[case::precision_4_decimals(vec![(InputType::Deposit, 1, 1, Some(dec!(1.2345))), (InputType::Withdrawal, 1, 2, Some(dec!(0.1234)))], 1, dec!(1.1111), dec!(0), dec!(1.1111), false, Ok(Applied::Withdrawal(dec!(0.1234))))]
+    #[case::missing_amount(vec![(InputType::Deposit, 1, 1, Some(dec!(10))), (InputType::Deposit, 1, 2, None)], 1, dec!(10), dec!(0), dec!(10), false, Err(TxError::MissingAmount))]
+    #[case::negative_deposit(vec![(InputType::Deposit, 1, 1, Some(dec!(10))), (InputType::Deposit, 1, 2, Some(dec!(-50)))], 1, dec!(10), dec!(0), dec!(10), false, Err(TxError::InvalidAmount))]
+    #[case::zero_deposit(vec![(InputType::Deposit, 1, 1, Some(dec!(10))), (InputType::Deposit, 1, 2, Some(dec!(0)))], 1, dec!(10), dec!(0), dec!(10), false, Err(TxError::InvalidAmount))]
+    #[case::negative_withdrawal(vec![(InputType::Deposit, 1, 1, Some(dec!(10))), (InputType::Withdrawal, 1, 2, Some(dec!(-100)))], 1, dec!(10), dec!(0), dec!(10), false, Err(TxError::InvalidAmount))]
+    #[case::duplicate_tx(vec![(InputType::Deposit, 1, 1, Some(dec!(10))), (InputType::Withdrawal, 1, 1, Some(dec!(5)))], 1, dec!(10), dec!(0), dec!(10), false, Err(TxError::DuplicateTx))]
+    #[case::chronological_order(vec![(InputType::Deposit, 1, 2, Some(dec!(10))), (InputType::Withdrawal, 1, 1, Some(dec!(8)))], 1, dec!(2), dec!(0), dec!(2), false, Ok(Applied::Withdrawal(dec!(8))))]
+    fn test_transactions(
+        #[case] txns: Vec<(InputType, u16, u32, Option<Decimal>)>,
+        #[case] client: u16,
+        #[case] expected_available: Decimal,
+        #[case] expected_held: Decimal,
+        #[case] expected_total: Decimal,
+        #[case] expected_locked: bool,
+        #[case] expected_last: Result<Applied, TxError>,
+    ) {
+        let mut engine = Engine::new();
+        let mut results: Vec<_> = txns
+            .into_iter()
+            .map(|(r#type, client, tx, amount)| {
+                engine.apply(Input::new(r#type, client, tx, amount))
+            })
+            .collect();
+        assert_eq!(results.pop(), Some(expected_last));
+        assert!(results.iter().all(Result::is_ok));
+        let acc = engine.account(client).unwrap();
+        assert_eq!(acc.available, expected_available);
+        assert_eq!(acc.held, expected_held);
+        assert_eq!(acc.total, expected_total);
+        assert_eq!(acc.locked, expected_locked);
+    }
+
+    impl quickcheck::Arbitrary for InputType {
+        fn arbitrary(g: &mut quickcheck::Gen) -> Self {
+            match u32::arbitrary(g) % 10 {
+                0 => InputType::Deposit,
+                1 => InputType::Withdrawal,
+                2 => InputType::Dispute,
+                3 => InputType::Resolve,
+                4 => InputType::Transfer,
+                5 => InputType::Authorize,
+                6 => InputType::Capture,
+                7 => InputType::Void,
+                8 => InputType::Refund,
+                _ => InputType::Chargeback,
+            }
+        }
+    }
+
+    impl quickcheck::Arbitrary for Input {
+        fn arbitrary(g: &mut quickcheck::Gen) -> Self {
+            let r#type = InputType::arbitrary(g);
+            let moves_money = matches!(
+                r#type,
+                InputType::Deposit
+                    | InputType::Withdrawal
+                    | InputType::Transfer
+                    | InputType::Authorize
+            );
+            let currency = if moves_money {
+                // disputes refer to the tx in its own currency
+                [None, Some("EUR"), Some("USD")][usize::arbitrary(g) % 3]
+                    .map(|code| code.parse().unwrap())
+            } else {
+                None
+            };
+            // small id ranges, so disputes regularly hit an earlier tx, and
+            // amounts at the 4 decimal precision of real input so sums are exact
+            Input {
+                currency,
+                destination: (r#type == InputType::Transfer).then(|| u16::arbitrary(g) % 10 + 1),
+                ..Input::new(
+                    r#type,
+                    u16::arbitrary(g) % 10 + 1,
+                    u32::arbitrary(g) % 100 + 1,
+                    // disputes, resolves, chargebacks, captures and refunds are
+                    // partial half of the time
+                    (moves_money || bool::arbitrary(g)).then(|| {
+                        let amount =
+                            Decimal::from_f64_retain(f64::arbitrary(g).abs() % 10000.0 + 0.01)
+                                .unwrap_or(Decimal::ONE)
+                                .round_dp(4);
+                        // now and then a zero or negative amount, which has
+                        // to be rejected
+                        match u8::arbitrary(g) % 20 {
+                            0 => Decimal::ZERO,
+                            1 => -amount,
+                            _ => amount,
+                        }
+                    }),
+                )
+            }
+        }
+    }
+
+    /// Holds deposits for a few transactions, and large ones for longer.
+    fn deposit_holds() -> FundsAvailability {
+        let mut holds = FundsAvailability::new();
+        for (from, transactions) in [(None, 3), (Some(dec!(5000)), 20)] {
+            holds
+                .insert(HoldRule {
+                    from,
+                    transactions: Some(transactions),
+                    seconds: None,
+                })
+                .unwrap();
+        }
+        holds
+    }
+
+    #[quickcheck_macros::quickcheck]
+    fn prop_total_equals_sum_of_balances(txns: Vec<Input>) -> bool {
+        [FundsAvailability::new(), deposit_holds()]
+            .into_iter()
+            .all(|funds_availability| {
+                let mut engine = Engine::with_config(Config {
+                    funds_availability,
+                    ..Default::default()
+                });
+                for txn in txns.iter().cloned() {
+                    let _ = engine.apply(txn);
+                }
+                engine.accounts().all(|acc| {
+                    acc.total
+                        == acc
+                            .available
+                            .saturating_add(acc.held)
+                            .saturating_add(acc.pending)
+                })
+            })
+    }
+
+    #[quickcheck_macros::quickcheck]
+    fn prop_no_negative_balances(txns: Vec<Input>) -> bool {
+        // odd clients have a credit line in some of their currencies
+        let mut overdrafts = OverdraftLimits::new();
+        for client in (1..=10).step_by(2) {
+            for currency in [None, Some("EUR".parse().unwrap())] {
+                let limit = Decimal::from(client) * dec!(100);
+                overdrafts
+                    .insert(OverdraftLimit {
+                        client,
+                        currency,
+                        limit,
+                    })
+                    .unwrap();
+            }
+        }
+        [
+            DisputePolicy::Disallow,
+            DisputePolicy::Hold,
+            DisputePolicy::ReversalCredit,
+        ]
+        .into_iter()
+        .flat_map(|withdrawal_disputes| {
+            [ShortfallPolicy::Reject, ShortfallPolicy::CapHold]
+                .map(|shortfall| (withdrawal_disputes, shortfall))
+        })
+        .all(|(withdrawal_disputes, shortfall)| {
+            let mut engine = Engine::with_config(Config {
+                withdrawal_disputes,
+                shortfall,
+                overdrafts: overdrafts.clone(),
+                funds_availability: deposit_holds(),
+                ..Default::default()
+            });
+            for txn in txns.iter().cloned() {
+                let _ = engine.apply(txn);
+            }
+            // balances only go negative as far as the overdraft allows
+            engine.accounts().all(|acc| {
+                let floor = -overdrafts.limit(acc.client, acc.currency);
+                acc.available >= floor
+                    && acc.held >= Decimal::ZERO
+                    && acc.pending >= Decimal::ZERO
+                    && acc.total >= floor
+            })
+        })
+    }
+
+    #[quickcheck_macros::quickcheck]
+    fn prop_journal_replays_balances(txns: Vec<Input>) -> bool {
+        let mut fees = FeeSchedule::new();
+        for r#type in [
+            InputType::Deposit,
+            InputType::Withdrawal,
+            InputType::Chargeback,
+        ] {
+            fees.insert(FeeRule {
+                r#type,
+                from: None,
+                flat: Some(dec!(0.5)),
+                percent: None,
+                min: None,
+                max: None,
+            })
+            .unwrap();
+        }
+        [DisputePolicy::Hold, DisputePolicy::ReversalCredit]
+            .into_iter()
+            .all(|withdrawal_disputes| {
+                let mut engine = Engine::with_config(Config {
+                    withdrawal_disputes,
+                    fees: fees.clone(),
+                    fee_refunds: FeeRefundPolicy::OnDispute,
+                    funds_availability: deposit_holds(),
+                    journal: true,
+                    ..Default::default()
+                });
+                let mut balances = HashMap::new();
+                let mut ledger = TrialBalance::new();
+                let mut balanced = true;
+                for txn in txns.iter().cloned() {
+                    let _ = engine.apply(txn);
+                    for event in engine.drain_events() {
+                        let Event::Journal(entry) = event else {
+                            continue;
+                        };
+                        balanced &= ledger.record(&entry).is_ok();
+                        for posting in entry.postings {
+                            let key = (posting.client, entry.currency, posting.account);
+                            *balances.entry(key).or_insert(Decimal::ZERO) +=
+                                posting.credit - posting.debit;
+                        }
+                    }
+                }
+                // the client postings add up to each account's balances, and
+                // the trial balance of each currency reconciles
+                let replayed = |acc: &Output, account| {
+                    let key = (Some(acc.client), acc.currency, account);
+                    balances.get(&key).copied().unwrap_or_default()
+                };
+                balanced
+                    && engine.accounts().all(|acc| {
+                        replayed(acc, LedgerAccount::Available) == acc.available
+                            && replayed(acc, LedgerAccount::Held) == acc.held
+                            && replayed(acc, LedgerAccount::Pending) == acc.pending
+                    })
+                    && ledger
+                        .reconcile(engine.accounts())
+                        .is_ok_and(|checks| checks.iter().all(Reconciliation::is_reconciled))
+            })
+    }
+
+    #[test]
+    fn test_spec_example() {
+        let csv = "type, client, tx, amount
+deposit, 1, 1, 1.0
+deposit, 2, 2, 2.0
+deposit, 1, 3, 2.0
+withdrawal, 1, 4, 1.5
+withdrawal, 2, 5, 3.0";
+
+        let mut rdr = csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_reader(csv.as_bytes());
+        let accounts = process_transactions(rdr.deserialize::<Input>().filter_map(Result::ok));
+
+        assert_eq!(
+            (accounts[&(1, None)].available, accounts[&(1, None)].total),
+            (dec!(1.5), dec!(1.5))
+        );
+        assert_eq!(
+            (accounts[&(2, None)].available, accounts[&(2, None)].total),
+            (dec!(2.0), dec!(2.0))
+        );
+    }
+
+    #[test]
+    #[ignore = "asserts on wall-clock time, run it in release mode"]
+    fn test_performance() {
+        use std::time::Instant;
+        let start = Instant::now();
+        let accounts = process_transactions((0..1_000_000).map(|i| {
+            Input::new(
+                if i % 2 == 0 {
+                    InputType::Deposit
+                } else {
+                    InputType::Withdrawal
+                },
+                (i % 10000) as u16,
+                i as u32,
+                Some(Decimal::from(i % 100 + 1)),
+            )
+        }));
+        assert!(start.elapsed().as_secs() < 2);
+        assert_eq!(accounts.len(), 10000);
+    }
+
+    #[quickcheck_macros::quickcheck]
+    fn prop_large_volume_benchmark(seed: u64) -> bool {
+        use std::time::Instant;
+        let txns = (0..100_000).map(|i| {
+            Input::new(
+                [
+                    InputType::Deposit,
+                    InputType::Withdrawal,
+                    InputType::Dispute,
+                    InputType::Resolve,
+                    InputType::Chargeback,
+                ][(seed.wrapping_add(i).wrapping_mul(7)) as usize % 5],
+                (seed.wrapping_add(i) % 1000 + 1) as u16,
+                i as u32,
+                matches!(
+                    [
+                        InputType::Deposit,
+                        InputType::Withdrawal,
+                        InputType::Dispute,
+                        InputType::Resolve,
+                        InputType::Chargeback
+                    ][(seed.wrapping_add(i).wrapping_mul(7)) as usize % 5],
+                    InputType::Deposit | InputType::Withdrawal
+                )
+                .then(|| Decimal::from(seed.wrapping_add(i) % 9999 + 1)),
+            )
+        });
+        let start = Instant::now();
+        let accounts = process_transactions(txns);
+        let elapsed = start.elapsed();
+        println!(
+            "Processed 100k transactions in {:?} ({:.0} tx/sec)",
+            elapsed,
+            100000.0 / elapsed.as_secs_f64()
+        );
+        accounts.values().all(|acc| {
+            acc.total
+                == acc
+                    .available
+                    .saturating_add(acc.held)
+                    .saturating_add(acc.pending)
+                && acc.available >= Decimal::ZERO
+                && acc.held >= Decimal::ZERO
+        })
+    }
+}
@@ -0,0 +1,75 @@
+use crate::Currency;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+
+/// One row of a limits file: `client` may overdraw their balance in
+/// `currency` (or the balance without one) by up to `limit`.
+#[derive(Debug, Deserialize, Clone, PartialEq, Eq)]
+pub struct OverdraftLimit {
+    pub client: u16,
+    pub currency: Option<Currency>,
+    pub limit: Decimal,
+}
+
+/// An overdraft limit below zero.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidLimit(pub OverdraftLimit);
+
+impl fmt::Display for InvalidLimit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "overdraft limit {} of client {} can't be negative",
+            self.0.limit, self.0.client
+        )
+    }
+}
+
+impl std::error::Error for InvalidLimit {}
+
+/// Approved credit lines per client and currency. Balances without a limit
+/// can't be overdrawn.
+#[derive(Debug, Default, Clone)]
+pub struct OverdraftLimits {
+    limits: HashMap<(u16, Option<Currency>), Decimal>,
+}
+
+impl OverdraftLimits {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets a limit, replacing any earlier one for the same balance.
+    pub fn insert(&mut self, limit: OverdraftLimit) -> Result<(), InvalidLimit> {
+        if limit.limit < Decimal::ZERO {
+            return Err(InvalidLimit(limit));
+        }
+        self.limits
+            .insert((limit.client, limit.currency), limit.limit);
+        Ok(())
+    }
+
+    /// How far below zero the balance may go.
+    pub fn limit(&self, client: u16, currency: Option<Currency>) -> Decimal {
+        self.limits
+            .get(&(client, currency))
+            .copied()
+            .unwrap_or_default()
+    }
+}
+
+/// An account whose available balance is below zero at the end of a run,
+/// whether by using its overdraft or through a dispute under
+/// [`ShortfallPolicy::AllowNegative`](crate::ShortfallPolicy::AllowNegative).
+#[derive(Debug, Serialize, Clone, PartialEq, Eq)]
+pub struct NegativeBalance {
+    pub client: u16,
+    pub currency: Option<Currency>,
+    pub available: Decimal,
+    pub total: Decimal,
+    pub limit: Decimal,
+    /// Whether the balance is below what the overdraft allows.
+    pub over_limit: bool,
+}
@@ -1,302 +1,426 @@
-use clap::Parser;
-use eyre::Result;
+use clap::{Parser, Subcommand};
+use csv_txn_simulator::{
+    Config, Currency, DisputePolicy, DuplicatePolicy, Engine, Event, FeeRefundPolicy, FeeSchedule,
+    FundsAvailability, InputReader, OverdraftLimits, OverflowPolicy, RateTable, Rejection,
+    RiskRules, ShortfallPolicy, TrialBalance, TxError, parse_rules,
+};
+use eyre::{Result, eyre};
 use rust_decimal::Decimal;
-use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
-use std::path::PathBuf;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
 
 #[derive(Parser, Debug)]
-#[command(name = "csv-txn-simulator")]
+#[command(
+    name = "csv-txn-simulator",
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true
+)]
 struct Args {
-    #[arg(value_name = "INPUT FILE")]
-    input_file: PathBuf,
-}
+    #[command(subcommand)]
+    command: Option<Command>,
 
-#[derive(Debug, Deserialize, Clone, Copy)]
-#[serde(rename_all = "lowercase")]
-enum InputType {
-    Deposit,
-    Withdrawal,
-    Dispute,
-    Resolve,
-    Chargeback,
-}
+    #[arg(value_name = "INPUT FILE", required = true)]
+    input_file: Option<PathBuf>,
 
-#[derive(Debug, Deserialize, Clone)]
-struct Input {
-    r#type: InputType,
-    client: u16,
-    tx: u32,
-    // Decimal is prefered for financial data because:
-    // 1. It avoids floating point errors
-    // 2. maintains the exact decimal representation.
-    // Alternative would be to use integers and track the decimal place/precision separately.
-    amount: Option<Decimal>,
-}
+    /// Write every ignored transaction, with the reason, to this csv file.
+    #[arg(long, value_name = "FILE")]
+    rejections: Option<PathBuf>,
 
-#[derive(Debug, Serialize, Default, Clone)]
-struct Output {
-    client: u16,
-    available: Decimal,
-    held: Decimal,
-    total: Decimal,
-    locked: bool,
-}
+    /// Write every dispute that exceeded the available balance to this csv file.
+    #[arg(long, value_name = "FILE")]
+    exposures: Option<PathBuf>,
 
-fn process_transactions(transactions: impl Iterator<Item = Input>) -> HashMap<u16, Output> {
-    let mut accounts: HashMap<u16, Output> = HashMap::new();
-    let mut txn_history: HashMap<u32, (u16, Decimal, bool)> = HashMap::new();
+    /// Write an audit record for every admin transaction to this csv file.
+    #[arg(long, value_name = "FILE")]
+    audit: Option<PathBuf>,
 
-    for txn in transactions {
-        let account = accounts.entry(txn.client).or_insert(Output {
-            client: txn.client,
-            ..Default::default()
-        });
+    /// Write every dispute that was resolved by --dispute-timeout to this csv file.
+    #[arg(long, value_name = "FILE")]
+    expired_disputes: Option<PathBuf>,
 
-        if account.locked {
-            continue;
-        }
+    /// Write every fee charged or refunded to this csv file.
+    #[arg(long, value_name = "FILE")]
+    fee_ledger: Option<PathBuf>,
 
-        match txn.r#type {
-            InputType::Deposit | InputType::Withdrawal => {
-                if let Some(amount) = txn.amount {
-                    let is_deposit = matches!(txn.r#type, InputType::Deposit);
-                    if is_deposit || account.available >= amount {
-                        let (add, sub) = if is_deposit {
-                            (amount, Decimal::ZERO)
-                        } else {
-                            (Decimal::ZERO, amount)
-                        };
-                        account.available =
-                            account.available.saturating_add(add).saturating_sub(sub);
-                        account.total = account.total.saturating_add(add).saturating_sub(sub);
-                        txn_history.insert(txn.tx, (txn.client, amount, false));
-                    }
-                }
-            }
-            _ => {
-                if let Some((client, amount, disputed)) = txn_history.get_mut(&txn.tx) {
-                    if *client == txn.client {
-                        match (txn.r#type, *disputed) {
-                            (InputType::Dispute, false) => {
-                                account.available = account.available.saturating_sub(*amount);
-                                account.held = account.held.saturating_add(*amount);
-                                *disputed = true;
-                            }
-                            (InputType::Resolve, true) => {
-                                account.available = account.available.saturating_add(*amount);
-                                account.held = account.held.saturating_sub(*amount);
-                                *disputed = false;
-                            }
-                            (InputType::Chargeback, true) => {
-                                account.held = account.held.saturating_sub(*amount);
-                                account.total = account.total.saturating_sub(*amount);
-                                account.locked = true;
-                            }
-                            _ => {}
-                        }
-                    }
-                }
-            }
-        }
-    }
+    /// Write every authorization voided by --auth-expiry or --auth-expiry-txns to this csv file.
+    #[arg(long, value_name = "FILE")]
+    expired_auths: Option<PathBuf>,
+
+    /// Fee schedule as a csv file with type, from, flat, percent, min and max.
+    #[arg(long, value_name = "FILE")]
+    fees: Option<PathBuf>,
+
+    /// When the fee on a disputed deposit or withdrawal is refunded.
+    #[arg(long, value_enum, default_value_t)]
+    fee_refunds: FeeRefundPolicy,
+
+    /// Overdraft limits as a csv file with client, an optional currency and limit.
+    #[arg(long, value_name = "FILE")]
+    limits: Option<PathBuf>,
+
+    /// Write every account left with a negative available balance to this csv file.
+    #[arg(long, value_name = "FILE")]
+    negative_balances: Option<PathBuf>,
+
+    /// Deposit holds as a csv file with from, transactions and seconds.
+    #[arg(long, value_name = "FILE")]
+    deposit_holds: Option<PathBuf>,
+
+    /// Risk rules as a csv file with id, rule, type, limit, seconds, transactions and action.
+    #[arg(long, value_name = "FILE")]
+    risk_rules: Option<PathBuf>,
+
+    /// Risk rules written in the rules language, one per line.
+    #[arg(long, value_name = "FILE")]
+    rules: Option<PathBuf>,
+
+    /// Write every risk rule hit, with the rule id, to this csv file.
+    #[arg(long, value_name = "FILE")]
+    risk_hits: Option<PathBuf>,
+
+    /// Write every balance movement as balanced debit and credit postings to this csv file.
+    #[arg(long, value_name = "FILE")]
+    journal: Option<PathBuf>,
+
+    /// Write every ledger account's balance at the end of the run to this csv file, and check
+    /// that they reconcile with the client totals.
+    #[arg(long, value_name = "FILE")]
+    trial_balance: Option<PathBuf>,
+
+    /// Exchange rates as a csv file with from, to, rate and an optional effective time.
+    #[arg(long, value_name = "FILE")]
+    fx_rates: Option<PathBuf>,
+
+    /// Print each client's total converted to this currency instead of their balances.
+    #[arg(long, value_name = "CODE", requires = "fx_rates")]
+    base_currency: Option<Currency>,
+
+    /// Abort on the first malformed row instead of logging it and carrying on.
+    #[arg(long)]
+    strict: bool,
+
+    /// How to handle deposits and withdrawals that reuse a known tx id.
+    #[arg(long, value_enum, default_value_t)]
+    duplicates: DuplicatePolicy,
+
+    /// How disputes on deposits are applied.
+    #[arg(long, value_enum, default_value_t = DisputePolicy::Hold)]
+    deposit_disputes: DisputePolicy,
+
+    /// How disputes on withdrawals are applied.
+    #[arg(long, value_enum, default_value_t = DisputePolicy::Hold)]
+    withdrawal_disputes: DisputePolicy,
+
+    /// How to handle a dispute that would hold more than the client has available.
+    #[arg(long, value_enum, default_value_t)]
+    shortfall: ShortfallPolicy,
+
+    /// How to handle a transaction that would overflow a balance.
+    #[arg(long, value_enum, default_value_t)]
+    overflow: OverflowPolicy,
+
+    /// Reject disputes filed more than this many seconds after their transaction.
+    #[arg(long, value_name = "SECONDS")]
+    dispute_window: Option<u64>,
+
+    /// Resolve disputes automatically once they have been open this many seconds.
+    #[arg(long, value_name = "SECONDS")]
+    dispute_timeout: Option<u64>,
+
+    /// How many times a resolved transaction may be disputed again [default: unlimited]
+    #[arg(long, value_name = "N")]
+    redispute_limit: Option<u32>,
+
+    /// Void authorizations automatically once they have been open this many seconds.
+    #[arg(long, value_name = "SECONDS")]
+    auth_expiry: Option<u64>,
+
+    /// Void authorizations automatically after this many later transactions.
+    #[arg(long, value_name = "N")]
+    auth_expiry_txns: Option<u64>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Work with rules files.
+    Rules {
+        #[command(subcommand)]
+        command: RulesCommand,
+    },
+}
 
-    accounts
+#[derive(Subcommand, Debug)]
+enum RulesCommand {
+    /// Validate rules files without running any transactions.
+    Check {
+        #[arg(value_name = "FILE", required = true)]
+        files: Vec<PathBuf>,
+    },
 }
 
-fn main() -> Result<()> {
+fn main() -> Result<ExitCode> {
     let args = Args::parse();
+    let input_file = match args.command {
+        Some(Command::Rules {
+            command: RulesCommand::Check { files },
+        }) => return check_rules(&files),
+        None => args.input_file.expect("required without a subcommand"),
+    };
 
     // the csv reader is buffered automatically,
     // with a reasonable buffer size.
-    let mut input_csv = csv::ReaderBuilder::new()
+    // flexible, so rows can leave out trailing optional columns
+    let input_csv = csv::ReaderBuilder::new()
         .trim(csv::Trim::All)
-        .from_path(args.input_file)?;
+        .flexible(true)
+        .from_path(input_file)?;
 
-    let accounts = process_transactions(input_csv.deserialize().filter_map(Result::ok));
+    let mut rates = RateTable::new();
+    if let Some(path) = &args.fx_rates {
+        load_csv(path, |rate| rates.insert(rate))?;
+    }
 
-    let mut wtr = csv::Writer::from_writer(std::io::stdout());
-    for account in accounts.values() {
-        wtr.serialize(account)?;
+    let mut fees = FeeSchedule::new();
+    if let Some(path) = &args.fees {
+        load_csv(path, |rule| fees.insert(rule))?;
     }
-    wtr.flush()?;
 
-    Ok(())
-}
+    let mut overdrafts = OverdraftLimits::new();
+    if let Some(path) = &args.limits {
+        load_csv(path, |limit| overdrafts.insert(limit))?;
+    }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use rstest::rstest;
-    use rust_decimal_macros::dec;
-
-    #[rstest]
-    #[case::deposit(vec![(InputType::Deposit, 1, 1, Some(dec!(10)))], 1, dec!(10), dec!(0), dec!(10), false)]
-    #[case::withdrawal_success(vec![(InputType::Deposit, 1, 1, Some(dec!(10))), (InputType::Withdrawal, 1, 2, Some(dec!(5)))], 1, dec!(5), dec!(0), dec!(5), false)]
-    #[case::withdrawal_insufficient(vec![(InputType::Deposit, 1, 1, Some(dec!(10))), (InputType::Withdrawal, 1, 2, Some(dec!(15)))], 1, dec!(10), dec!(0), dec!(10), false)]
-    #[case::dispute(vec![(InputType::Deposit, 1, 1, Some(dec!(10))), (InputType::Dispute, 1, 1, None)], 1, dec!(0), dec!(10), dec!(10), false)]
-    #[case::resolve(vec![(InputType::Deposit, 1, 1, Some(dec!(10))), (InputType::Dispute, 1, 1, None), (InputType::Resolve, 1, 1, None)], 1, dec!(10), dec!(0), dec!(10), false)]
-    #[case::chargeback(vec![(InputType::Deposit, 1, 1, Some(dec!(10))), (InputType::Dispute, 1, 1, None), (InputType::Chargeback, 1, 1, None)], 1, dec!(0), dec!(0), dec!(0), true)]
-    #[case::locked_ignores_txns(vec![(InputType::Deposit, 1, 1, Some(dec!(10))), (InputType::Dispute, 1, 1, None), (InputType::Chargeback, 1, 1, None), (InputType::Deposit, 1, 2, Some(dec!(5)))], 1, dec!(0), dec!(0), dec!(0), true)]
-    #[case::dispute_nonexistent(vec![(InputType::Deposit, 1, 1, Some(dec!(10))), (InputType::Dispute, 1, 999, None)], 1, dec!(10), dec!(0), dec!(10), false)]
-    #[case::double_dispute(vec![(InputType::Deposit, 1, 1, Some(dec!(10))), (InputType::Dispute, 1, 1, None), (InputType::Dispute, 1, 1, None)], 1, dec!(0), dec!(10), dec!(10), false)]
-    #[case::resolve_non_disputed(vec![(InputType::Deposit, 1, 1, Some(dec!(10))), (InputType::Resolve, 1, 1, None)], 1, dec!(10), dec!(0), dec!(10), false)]
-    #[case::chargeback_non_disputed(vec![(InputType::Deposit, 1, 1, Some(dec!(10))), (InputType::Chargeback, 1, 1, None)], 1, dec!(10), dec!(0), dec!(10), false)]
-    #[case::dispute_withdrawal(vec![(InputType::Deposit, 1, 1, Some(dec!(10))), (InputType::Withdrawal, 1, 2, Some(dec!(5))), (InputType::Dispute, 1, 2, None)], 1, dec!(0), dec!(5), dec!(5), false)]
-    #[case::multiple_clients(vec![(InputType::Deposit, 1, 1, Some(dec!(10))), (InputType::Deposit, 2, 2, Some(dec!(20))), (InputType::Withdrawal, 1, 3, Some(dec!(5)))], 1, dec!(5), dec!(0), dec!(5), false)]
-    #[case::saturation(vec![(InputType::Deposit, 1, 1, Some(Decimal::MAX)), (InputType::Deposit, 1, 2, Some(dec!(1)))], 1, Decimal::MAX, dec!(0), Decimal::MAX, false)]
-    #[case::cross_client_dispute(vec![(InputType::Deposit, 1, 1, Some(dec!(10))), (InputType::Dispute, 2, 1, None)], 1, dec!(10), dec!(0), dec!(10), false)]
-    #[case::precision_4_decimals(vec![(InputType::Deposit, 1, 1, Some(dec!(1.2345))), (InputType::Withdrawal, 1, 2, Some(dec!(0.1234)))], 1, dec!(1.1111), dec!(0), dec!(1.1111), false)]
-    #[case::chronological_order(vec![(InputType::Deposit, 1, 2, Some(dec!(10))), (InputType::Withdrawal, 1, 1, Some(dec!(8)))], 1, dec!(2), dec!(0), dec!(2), false)]
-    fn test_transactions(
-        #[case] txns: Vec<(InputType, u16, u32, Option<Decimal>)>,
-        #[case] client: u16,
-        #[case] expected_available: Decimal,
-        #[case] expected_held: Decimal,
-        #[case] expected_total: Decimal,
-        #[case] expected_locked: bool,
-    ) {
-        let inputs: Vec<_> = txns
-            .into_iter()
-            .map(|(r#type, client, tx, amount)| Input {
-                r#type,
-                client,
-                tx,
-                amount,
-            })
-            .collect();
-        let accounts = process_transactions(inputs.into_iter());
-        let acc = &accounts[&client];
-        assert_eq!(acc.available, expected_available);
-        assert_eq!(acc.held, expected_held);
-        assert_eq!(acc.total, expected_total);
-        assert_eq!(acc.locked, expected_locked);
+    let mut funds_availability = FundsAvailability::new();
+    if let Some(path) = &args.deposit_holds {
+        load_csv(path, |rule| funds_availability.insert(rule))?;
     }
 
-    impl quickcheck::Arbitrary for InputType {
-        fn arbitrary(g: &mut quickcheck::Gen) -> Self {
-            match u32::arbitrary(g) % 5 {
-                0 => InputType::Deposit,
-                1 => InputType::Withdrawal,
-                2 => InputType::Dispute,
-                3 => InputType::Resolve,
-                _ => InputType::Chargeback,
+    let mut risk = RiskRules::new();
+    if let Some(path) = &args.risk_rules {
+        load_csv(path, |rule| risk.insert(rule))?;
+    }
+    if let Some(path) = &args.rules {
+        for rule in parse_rules(&std::fs::read_to_string(path)?)? {
+            risk.insert_script(rule);
+        }
+    }
+
+    let mut rejections = args.rejections.map(csv::Writer::from_path).transpose()?;
+    let mut exposures = args.exposures.map(csv::Writer::from_path).transpose()?;
+    let mut audit = args.audit.map(csv::Writer::from_path).transpose()?;
+    let mut expired = args
+        .expired_disputes
+        .map(csv::Writer::from_path)
+        .transpose()?;
+    let mut expired_auths = args.expired_auths.map(csv::Writer::from_path).transpose()?;
+    let mut fee_ledger = args.fee_ledger.map(csv::Writer::from_path).transpose()?;
+    let mut risk_hits = args.risk_hits.map(csv::Writer::from_path).transpose()?;
+    let journaled = args.journal.is_some() || args.trial_balance.is_some();
+    let mut journal = args.journal.map(csv::Writer::from_path).transpose()?;
+    let mut ledger = args.trial_balance.is_some().then(TrialBalance::new);
+
+    let mut engine = Engine::with_config(Config {
+        duplicates: args.duplicates,
+        deposit_disputes: args.deposit_disputes,
+        withdrawal_disputes: args.withdrawal_disputes,
+        shortfall: args.shortfall,
+        overflow: args.overflow,
+        dispute_window: args.dispute_window,
+        dispute_timeout: args.dispute_timeout,
+        redispute_limit: args.redispute_limit,
+        auth_expiry: args.auth_expiry,
+        auth_expiry_txns: args.auth_expiry_txns,
+        fees,
+        fee_refunds: args.fee_refunds,
+        overdrafts,
+        funds_availability,
+        risk,
+        journal: journaled,
+    });
+    let mut parse_errors = 0u64;
+    let mut abort = None;
+    for row in InputReader::new(input_csv)? {
+        let txn = match row {
+            Ok(txn) => txn,
+            Err(err) if args.strict => return Err(err.into()),
+            Err(err) => {
+                eprintln!("skipping malformed row: {err}");
+                parse_errors += 1;
+                continue;
+            }
+        };
+        let (tx, client) = (txn.tx, txn.client);
+        let result = engine.apply(txn);
+        if let Err(reason) = result {
+            log(&mut rejections, Rejection { tx, client, reason })?;
+        }
+        let mut overflowed = (result == Err(TxError::Overflow)).then_some((tx, client));
+        for event in engine.drain_events() {
+            match event {
+                Event::Exposure(exposure) => log(&mut exposures, exposure)?,
+                Event::Audit(record) => log(&mut audit, record)?,
+                Event::DisputeExpired(dispute) => log(&mut expired, dispute)?,
+                Event::AuthExpired(auth) => log(&mut expired_auths, auth)?,
+                Event::Fee(entry) => log(&mut fee_ledger, entry)?,
+                Event::RiskHit(hit) => log(&mut risk_hits, hit)?,
+                Event::Journal(entry) => {
+                    match &mut ledger {
+                        Some(ledger) => ledger.record(&entry)?,
+                        None => entry.check()?,
+                    }
+                    for row in entry.rows() {
+                        log(&mut journal, row)?;
+                    }
+                }
+                Event::Saturated { tx, client } => {
+                    let reason = TxError::Overflow;
+                    log(&mut rejections, Rejection { tx, client, reason })?;
+                }
+                Event::Deferred { tx, client } => {
+                    let reason = TxError::Overflow;
+                    log(&mut rejections, Rejection { tx, client, reason })?;
+                    overflowed.get_or_insert((tx, client));
+                }
             }
         }
+        if let Some((tx, client)) = overflowed
+            && args.overflow == OverflowPolicy::Abort
+        {
+            abort = Some(eyre!("tx {tx} of client {client} would overflow a balance"));
+            break;
+        }
+    }
+    for wtr in [
+        rejections.as_mut(),
+        exposures.as_mut(),
+        audit.as_mut(),
+        expired.as_mut(),
+        expired_auths.as_mut(),
+        fee_ledger.as_mut(),
+        risk_hits.as_mut(),
+        journal.as_mut(),
+    ]
+    .into_iter()
+    .flatten()
+    {
+        wtr.flush()?;
+    }
+    if let Some(err) = abort {
+        return Err(err);
+    }
+
+    if let Some(path) = args.negative_balances {
+        let mut wtr = csv::Writer::from_path(path)?;
+        for row in engine.negative_balances() {
+            wtr.serialize(row)?;
+        }
+        wtr.flush()?;
     }
 
-    impl quickcheck::Arbitrary for Input {
-        fn arbitrary(g: &mut quickcheck::Gen) -> Self {
-            let r#type = InputType::arbitrary(g);
-            Input {
-                r#type,
-                client: u16::arbitrary(g) % 100 + 1,
-                tx: u32::arbitrary(g) % 10000 + 1,
-                amount: matches!(r#type, InputType::Deposit | InputType::Withdrawal).then(|| {
-                    Decimal::from_f64_retain(f64::arbitrary(g).abs() % 10000.0 + 0.01)
-                        .unwrap_or(Decimal::ONE)
-                }),
+    if let (Some(path), Some(ledger)) = (args.trial_balance, ledger) {
+        let mut wtr = csv::Writer::from_path(path)?;
+        for row in ledger.rows() {
+            wtr.serialize(row)?;
+        }
+        wtr.flush()?;
+        for check in ledger.reconcile(engine.accounts())? {
+            let currency = check
+                .currency
+                .map_or("no currency".to_string(), |c| c.to_string());
+            eprintln!(
+                "trial balance in {currency}: client totals {} = deposits {} + refunds {} - withdrawals {} - captures {} - chargebacks {} - fees {}",
+                check.client_totals,
+                check.deposits,
+                check.refunds,
+                check.withdrawals,
+                check.captures,
+                check.chargebacks,
+                check.fees
+            );
+            if !check.is_reconciled() {
+                return Err(eyre!("trial balance in {currency} doesn't reconcile"));
             }
         }
     }
 
-    #[quickcheck_macros::quickcheck]
-    fn prop_total_equals_available_plus_held(txns: Vec<Input>) -> bool {
-        let accounts = process_transactions(txns.into_iter());
-        accounts
-            .values()
-            .all(|acc| acc.total == acc.available.saturating_add(acc.held))
+    let mut wtr = csv::Writer::from_writer(std::io::stdout());
+    if let Some(base) = args.base_currency {
+        let report = engine.consolidated(&rates, base)?;
+        let residual: Decimal = report.iter().map(|row| row.residual).sum();
+        for row in report {
+            wtr.serialize(row)?;
+        }
+        eprintln!("rounding residual in {base}: {residual}");
+    } else {
+        for account in engine.accounts() {
+            wtr.serialize(account)?;
+        }
     }
+    wtr.flush()?;
 
-    #[quickcheck_macros::quickcheck]
-    fn prop_no_negative_balances(txns: Vec<Input>) -> bool {
-        let accounts = process_transactions(txns.into_iter());
-        accounts.values().all(|acc| {
-            acc.available >= Decimal::ZERO
-                && acc.held >= Decimal::ZERO
-                && acc.total >= Decimal::ZERO
-        })
+    let summary = engine.summary();
+    eprintln!(
+        "applied: {}, rejected: {}, duplicate tx ids: {}, malformed rows: {parse_errors}",
+        summary.applied, summary.rejected, summary.duplicates
+    );
+    if parse_errors > 0 {
+        return Ok(ExitCode::FAILURE);
     }
+    Ok(ExitCode::SUCCESS)
+}
 
-    #[test]
-    fn test_spec_example() {
-        let csv = "type, client, tx, amount
-deposit, 1, 1, 1.0
-deposit, 2, 2, 2.0
-deposit, 1, 3, 2.0
-withdrawal, 1, 4, 1.5
-withdrawal, 2, 5, 3.0";
-
-        let mut rdr = csv::ReaderBuilder::new()
-            .trim(csv::Trim::All)
-            .from_reader(csv.as_bytes());
-        let accounts = process_transactions(rdr.deserialize::<Input>().filter_map(Result::ok));
-
-        assert_eq!(
-            (accounts[&1].available, accounts[&1].total),
-            (dec!(1.5), dec!(1.5))
-        );
-        assert_eq!(
-            (accounts[&2].available, accounts[&2].total),
-            (dec!(2.0), dec!(2.0))
-        );
+/// Parses every rules file, reporting each one's rule count or first error.
+fn check_rules(files: &[PathBuf]) -> Result<ExitCode> {
+    let mut valid = true;
+    for path in files {
+        match parse_rules(&std::fs::read_to_string(path)?) {
+            Ok(rules) => println!("{}: {} rules ok", path.display(), rules.len()),
+            Err(err) => {
+                eprintln!("{}: {err}", path.display());
+                valid = false;
+            }
+        }
     }
+    Ok(if valid {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    })
+}
 
-    #[test]
-    fn test_performance() {
-        use std::time::Instant;
-        let start = Instant::now();
-        let accounts = process_transactions((0..1_000_000).map(|i| Input {
-            r#type: if i % 2 == 0 {
-                InputType::Deposit
-            } else {
-                InputType::Withdrawal
-            },
-            client: (i % 10000) as u16,
-            tx: i as u32,
-            amount: Some(Decimal::from(i % 100 + 1)),
-        }));
-        assert!(start.elapsed().as_secs() < 2);
-        assert_eq!(accounts.len(), 10000);
+/// Reads a config csv file, passing each row to `insert`. Like the input,
+/// rows may leave out trailing optional columns. Errors name the file and,
+/// for a bad row, its line.
+fn load_csv<T, E>(path: &Path, mut insert: impl FnMut(T) -> Result<(), E>) -> Result<()>
+where
+    T: DeserializeOwned,
+    E: std::fmt::Display,
+{
+    let in_file = |err: csv::Error| eyre!("{}: {err}", path.display());
+    let mut rdr = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .flexible(true)
+        .from_path(path)
+        .map_err(in_file)?;
+    let headers = rdr.headers().map_err(in_file)?.clone();
+    for record in rdr.records() {
+        let record = record.map_err(in_file)?;
+        let line = record.position().map_or(0, csv::Position::line);
+        let on_line = |err: &dyn std::fmt::Display| eyre!("{}: line {line}: {err}", path.display());
+        let row = record
+            .deserialize(Some(&headers))
+            .map_err(|err| on_line(&err))?;
+        insert(row).map_err(|err| on_line(&err))?;
     }
+    Ok(())
+}
 
-    #[quickcheck_macros::quickcheck]
-    fn prop_large_volume_benchmark(seed: u64) -> bool {
-        use std::time::Instant;
-        let txns = (0..100_000).map(|i| Input {
-            r#type: [
-                InputType::Deposit,
-                InputType::Withdrawal,
-                InputType::Dispute,
-                InputType::Resolve,
-                InputType::Chargeback,
-            ][(seed.wrapping_add(i).wrapping_mul(7)) as usize % 5],
-            client: (seed.wrapping_add(i) % 1000 + 1) as u16,
-            tx: i as u32,
-            amount: matches!(
-                [
-                    InputType::Deposit,
-                    InputType::Withdrawal,
-                    InputType::Dispute,
-                    InputType::Resolve,
-                    InputType::Chargeback
-                ][(seed.wrapping_add(i).wrapping_mul(7)) as usize % 5],
-                InputType::Deposit | InputType::Withdrawal
-            )
-            .then(|| Decimal::from(seed.wrapping_add(i) % 9999 + 1)),
-        });
-        let start = Instant::now();
-        let accounts = process_transactions(txns);
-        let elapsed = start.elapsed();
-        println!(
-            "Processed 100k transactions in {:?} ({:.0} tx/sec)",
-            elapsed,
-            100000.0 / elapsed.as_secs_f64()
-        );
-        accounts.values().all(|acc| {
-            acc.total == acc.available.saturating_add(acc.held)
-                && acc.available >= Decimal::ZERO
-                && acc.held >= Decimal::ZERO
-        })
+/// Appends a row to an optional csv log.
+fn log<W: std::io::Write>(wtr: &mut Option<csv::Writer<W>>, row: impl Serialize) -> Result<()> {
+    if let Some(wtr) = wtr {
+        wtr.serialize(row)?;
     }
+    Ok(())
 }
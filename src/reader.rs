@@ -0,0 +1,108 @@
+use crate::Input;
+use std::fmt;
+use std::io;
+
+/// A csv row that could not be turned into an [`Input`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    /// 1-based line number in the source file, including the header.
+    pub line: u64,
+    /// The row as it was read, fields re-joined with commas. Empty when the
+    /// row itself could not be read (e.g. invalid utf-8).
+    pub record: String,
+    pub error: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {} (`{}`)", self.line, self.error, self.record)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// Streams [`Input`]s out of a csv source, yielding a [`ParseError`] for
+/// every row that doesn't deserialize instead of silently skipping it.
+pub struct InputReader<R> {
+    reader: csv::Reader<R>,
+    headers: csv::StringRecord,
+    record: csv::StringRecord,
+}
+
+impl<R: io::Read> InputReader<R> {
+    /// Wraps a csv reader, reading its header row up front.
+    pub fn new(mut reader: csv::Reader<R>) -> csv::Result<Self> {
+        let headers = reader.headers()?.clone();
+        Ok(Self {
+            reader,
+            headers,
+            record: csv::StringRecord::new(),
+        })
+    }
+}
+
+impl<R: io::Read> Iterator for InputReader<R> {
+    type Item = Result<Input, ParseError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.reader.read_record(&mut self.record) {
+            Ok(false) => None,
+            Ok(true) => {
+                Some(
+                    self.record
+                        .deserialize(Some(&self.headers))
+                        .map_err(|err| ParseError {
+                            line: self.record.position().map_or(0, |pos| pos.line()),
+                            record: self.record.iter().collect::<Vec<_>>().join(","),
+                            error: describe(&err),
+                        }),
+                )
+            }
+            Err(err) => Some(Err(ParseError {
+                line: err.position().map_or(0, |pos| pos.line()),
+                record: String::new(),
+                error: describe(&err),
+            })),
+        }
+    }
+}
+
+/// The serde error without the position prefix csv adds, since [`ParseError`]
+/// already carries the line.
+fn describe(err: &csv::Error) -> String {
+    match err.kind() {
+        csv::ErrorKind::Deserialize { err, .. } => err.kind().to_string(),
+        _ => err.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reports_bad_rows_and_keeps_going() {
+        let csv = "type, client, tx, amount
+deposit, 1, 1, 1.0
+depositt, 1, 2, 1.0
+withdrawal, 1, 3, abc
+withdrawal, 1, 4, 0.5";
+        let rdr = csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_reader(csv.as_bytes());
+        let results: Vec<_> = InputReader::new(rdr).unwrap().collect();
+
+        assert_eq!(results.len(), 4);
+        assert!(results[0].is_ok());
+        assert!(results[3].is_ok());
+
+        let bad_type = results[1].as_ref().unwrap_err();
+        assert_eq!(bad_type.line, 3);
+        assert_eq!(bad_type.record, "depositt,1,2,1.0");
+        assert!(bad_type.error.contains("depositt"));
+
+        let bad_amount = results[2].as_ref().unwrap_err();
+        assert_eq!(bad_amount.line, 4);
+        assert_eq!(bad_amount.record, "withdrawal,1,3,abc");
+    }
+}
@@ -0,0 +1,380 @@
+use crate::{Currency, Input, InputType, ScriptRule};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::fmt;
+
+/// What a [`RiskRule`] looks at.
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum RiskCheck {
+    /// At most `limit` transactions of the rule's type per client within the
+    /// window, counting the one being checked.
+    MaxCount,
+    /// At most `limit` moved by transactions of the rule's type per client
+    /// and currency within the window, counting the one being checked.
+    MaxAmount,
+    /// No transaction of the rule's type within the window after the
+    /// client's latest deposit.
+    Cooldown,
+}
+
+/// What happens to a transaction that hits a [`RiskRule`].
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum RiskAction {
+    /// Reject the transaction.
+    Reject,
+    /// Apply the transaction, but flag the account for review.
+    Flag,
+}
+
+/// One row of a risk rule file. The window is either the last
+/// `transactions` transactions the engine processed or the last `seconds`
+/// before the transaction's timestamp; time windows only apply to
+/// timestamped transactions.
+#[derive(Debug, Deserialize, Clone, PartialEq, Eq)]
+pub struct RiskRule {
+    pub id: String,
+    pub rule: RiskCheck,
+    pub r#type: InputType,
+    pub limit: Option<Decimal>,
+    pub seconds: Option<u64>,
+    pub transactions: Option<u64>,
+    pub action: RiskAction,
+}
+
+impl RiskRule {
+    /// Whether something seen at `seq` and `at` is within the window of a
+    /// transaction seen at `now_seq` and `now`.
+    fn in_window(&self, seq: u64, at: Option<u64>, now_seq: u64, now: Option<u64>) -> bool {
+        match (self.transactions, self.seconds) {
+            (Some(transactions), _) => now_seq - seq < transactions,
+            (_, Some(seconds)) => {
+                matches!((at, now), (Some(at), Some(now)) if now.saturating_sub(at) < seconds)
+            }
+            (None, None) => false,
+        }
+    }
+}
+
+/// A risk rule without exactly one window, or without a valid limit for
+/// its check.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidRiskRule(pub RiskRule);
+
+impl fmt::Display for InvalidRiskRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid risk rule {}", self.0.id)
+    }
+}
+
+impl std::error::Error for InvalidRiskRule {}
+
+/// The risk rules every transaction is screened against before it is
+/// applied. Empty by default.
+#[derive(Debug, Default, Clone)]
+pub struct RiskRules {
+    rules: Vec<RiskRule>,
+    /// Rules written in the [rules language](crate::parse_rules).
+    scripts: Vec<ScriptRule>,
+    /// The longest transaction and time windows of any rule, beyond which
+    /// past activity can be forgotten.
+    max_transactions: u64,
+    max_seconds: u64,
+}
+
+impl RiskRules {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty() && self.scripts.is_empty()
+    }
+
+    /// Adds a rule from the rules language, checked after the window rules.
+    pub fn insert_script(&mut self, rule: ScriptRule) {
+        self.scripts.push(rule);
+    }
+
+    pub(crate) fn scripts(&self) -> &[ScriptRule] {
+        &self.scripts
+    }
+
+    /// Adds a rule. Rules are checked in the order they were added.
+    pub fn insert(&mut self, rule: RiskRule) -> Result<(), InvalidRiskRule> {
+        let one_window = rule.transactions.is_some() != rule.seconds.is_some();
+        let valid_limit = match rule.rule {
+            RiskCheck::MaxCount => rule
+                .limit
+                .is_some_and(|limit| limit >= Decimal::ZERO && limit.fract().is_zero()),
+            RiskCheck::MaxAmount => rule.limit.is_some_and(|limit| limit >= Decimal::ZERO),
+            RiskCheck::Cooldown => true,
+        };
+        if !one_window || !valid_limit {
+            return Err(InvalidRiskRule(rule));
+        }
+        self.max_transactions = self
+            .max_transactions
+            .max(rule.transactions.unwrap_or_default());
+        self.max_seconds = self.max_seconds.max(rule.seconds.unwrap_or_default());
+        self.rules.push(rule);
+        Ok(())
+    }
+}
+
+/// A transaction that hit a risk rule, and what was done about it.
+#[derive(Debug, Serialize, Clone, PartialEq, Eq)]
+pub struct RiskHit {
+    pub tx: u32,
+    pub client: u16,
+    pub rule: String,
+    pub action: RiskAction,
+}
+
+/// An applied transaction, as far as risk rules care.
+#[derive(Debug, Clone)]
+pub(crate) struct Activity {
+    r#type: InputType,
+    amount: Decimal,
+    currency: Option<Currency>,
+    timestamp: Option<u64>,
+    /// How many transactions the engine had seen before this one.
+    seq: u64,
+}
+
+impl Activity {
+    pub(crate) fn new(txn: &Input, seq: u64) -> Self {
+        Self {
+            r#type: txn.r#type,
+            amount: txn.amount.unwrap_or_default(),
+            currency: txn.currency,
+            timestamp: txn.timestamp,
+            seq,
+        }
+    }
+}
+
+/// A client's recent applied transactions.
+#[derive(Debug, Default)]
+pub(crate) struct ClientActivity {
+    recent: VecDeque<Activity>,
+    last_deposit: Option<(u64, Option<u64>)>,
+}
+
+impl ClientActivity {
+    /// The rules `next` would break.
+    pub(crate) fn hits<'a>(
+        &self,
+        rules: &'a RiskRules,
+        next: &Activity,
+    ) -> impl Iterator<Item = &'a RiskRule> {
+        rules.rules.iter().filter(move |rule| {
+            if rule.r#type != next.r#type {
+                return false;
+            }
+            let window = self.recent.iter().filter(|past| {
+                past.r#type == rule.r#type
+                    && rule.in_window(past.seq, past.timestamp, next.seq, next.timestamp)
+            });
+            let limit = rule.limit.unwrap_or_default();
+            match rule.rule {
+                RiskCheck::MaxCount => Decimal::from(window.count() + 1) > limit,
+                RiskCheck::MaxAmount => {
+                    let moved = window
+                        .filter(|past| past.currency == next.currency)
+                        .fold(next.amount, |sum, past| sum.saturating_add(past.amount));
+                    moved > limit
+                }
+                RiskCheck::Cooldown => self
+                    .last_deposit
+                    .is_some_and(|(seq, at)| rule.in_window(seq, at, next.seq, next.timestamp)),
+            }
+        })
+    }
+
+    /// Remembers an applied transaction, forgetting whatever has fallen out
+    /// of every rule's window.
+    pub(crate) fn record(&mut self, rules: &RiskRules, activity: Activity) {
+        let expired = |past: &Activity| {
+            let by_count = activity.seq - past.seq >= rules.max_transactions;
+            let by_time = rules.max_seconds == 0
+                || match (past.timestamp, activity.timestamp) {
+                    (Some(at), Some(now)) => now.saturating_sub(at) >= rules.max_seconds,
+                    (Some(_), None) => false,
+                    (None, _) => true,
+                };
+            by_count && by_time
+        };
+        while self.recent.front().is_some_and(expired) {
+            self.recent.pop_front();
+        }
+        if activity.r#type == InputType::Deposit {
+            self.last_deposit = Some((activity.seq, activity.timestamp));
+        }
+        self.recent.push_back(activity);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Config, Engine, Event, TxError};
+    use rstest::rstest;
+    use rust_decimal_macros::dec;
+
+    fn rule(check: RiskCheck, limit: Option<Decimal>, action: RiskAction) -> RiskRule {
+        RiskRule {
+            id: "r1".to_string(),
+            rule: check,
+            r#type: InputType::Withdrawal,
+            limit,
+            seconds: None,
+            transactions: Some(3),
+            action,
+        }
+    }
+
+    #[rstest]
+    // the third withdrawal within three transactions is one too many
+    #[case::max_count(rule(RiskCheck::MaxCount, Some(dec!(2)), RiskAction::Reject), vec![(1, 1), (1, 1), (1, 1), (2, 1)], vec![Ok(()), Ok(()), Err(TxError::RiskRejected), Ok(())])]
+    #[case::max_amount(rule(RiskCheck::MaxAmount, Some(dec!(5)), RiskAction::Reject), vec![(1, 3), (1, 3), (2, 3), (1, 2)], vec![Ok(()), Err(TxError::RiskRejected), Ok(()), Ok(())])]
+    #[case::cooldown(rule(RiskCheck::Cooldown, None, RiskAction::Reject), vec![(1, 1), (1, 1), (1, 1), (2, 1)], vec![Err(TxError::RiskRejected), Ok(()), Ok(()), Ok(())])]
+    #[case::flag(rule(RiskCheck::MaxCount, Some(dec!(0)), RiskAction::Flag), vec![(1, 1)], vec![Ok(())])]
+    fn test_risk_rules(
+        #[case] rule: RiskRule,
+        #[case] withdrawals: Vec<(u16, i64)>,
+        #[case] expected: Vec<Result<(), TxError>>,
+    ) {
+        let mut risk = RiskRules::new();
+        let action = rule.action;
+        risk.insert(rule).unwrap();
+        let mut engine = Engine::with_config(Config {
+            risk,
+            ..Config::default()
+        });
+        for client in [1, 2] {
+            let deposit = Input::new(InputType::Deposit, client, client.into(), Some(dec!(100)));
+            engine.apply(deposit).unwrap();
+        }
+        let results: Vec<_> = withdrawals
+            .into_iter()
+            .zip(10..)
+            .map(|((client, amount), tx)| {
+                let withdrawal = Input::new(
+                    InputType::Withdrawal,
+                    client,
+                    tx,
+                    Some(Decimal::from(amount)),
+                );
+                engine.apply(withdrawal).map(drop)
+            })
+            .collect();
+        assert_eq!(results, expected);
+
+        let hits: Vec<_> = engine
+            .drain_events()
+            .filter_map(|event| match event {
+                Event::RiskHit(hit) => Some(hit),
+                _ => None,
+            })
+            .collect();
+        assert!(
+            hits.iter()
+                .all(|hit| hit.rule == "r1" && hit.action == action)
+        );
+        let rejected = expected.iter().filter(|result| result.is_err()).count();
+        if action == RiskAction::Reject {
+            assert_eq!(hits.len(), rejected);
+        } else {
+            assert!(engine.account(1).unwrap().flagged);
+        }
+    }
+
+    #[test]
+    fn test_invalid_risk_rules() {
+        let mut risk = RiskRules::new();
+        let no_limit = rule(RiskCheck::MaxCount, None, RiskAction::Reject);
+        assert_eq!(
+            risk.insert(no_limit.clone()),
+            Err(InvalidRiskRule(no_limit))
+        );
+        let two_windows = RiskRule {
+            seconds: Some(60),
+            ..rule(RiskCheck::Cooldown, None, RiskAction::Reject)
+        };
+        assert_eq!(
+            risk.insert(two_windows.clone()),
+            Err(InvalidRiskRule(two_windows))
+        );
+        assert!(risk.is_empty());
+    }
+
+    #[test]
+    fn test_script_rules_see_the_account() {
+        let mut risk = RiskRules::new();
+        risk.insert_script(
+            r#"type == "withdrawal" && amount >= 50 && account.available < 100 => reject("thin")"#
+                .parse()
+                .unwrap(),
+        );
+        let mut engine = Engine::with_config(Config {
+            risk,
+            ..Config::default()
+        });
+        engine
+            .apply(Input::new(InputType::Deposit, 1, 1, Some(dec!(120))))
+            .unwrap();
+        engine
+            .apply(Input::new(InputType::Withdrawal, 1, 2, Some(dec!(50))))
+            .unwrap();
+        assert_eq!(
+            engine.apply(Input::new(InputType::Withdrawal, 1, 3, Some(dec!(50)))),
+            Err(TxError::RiskRejected)
+        );
+        let hit = engine.drain_events().find_map(|event| match event {
+            Event::RiskHit(hit) => Some(hit),
+            _ => None,
+        });
+        assert_eq!(
+            hit.map(|hit| (hit.tx, hit.rule)),
+            Some((3, "thin".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_locked_accounts_are_not_screened() {
+        let mut risk = RiskRules::new();
+        for rule in [
+            r#"type == "withdrawal" => reject("no_withdrawals")"#,
+            r#"type == "deposit" => flag("deposits")"#,
+        ] {
+            risk.insert_script(rule.parse().unwrap());
+        }
+        let mut engine = Engine::with_config(Config {
+            risk,
+            ..Config::default()
+        });
+        let freeze = Input {
+            actor: Some("ops".to_string()),
+            ..Input::new(InputType::Freeze, 1, 1, None)
+        };
+        engine.apply(freeze).unwrap();
+        // the account's own lock is what rejects these, and no rule is hit
+        assert_eq!(
+            engine.apply(Input::new(InputType::Withdrawal, 1, 2, Some(dec!(5)))),
+            Err(TxError::AccountLocked)
+        );
+        assert_eq!(
+            engine.apply(Input::new(InputType::Deposit, 1, 3, Some(dec!(5)))),
+            Err(TxError::AccountLocked)
+        );
+        assert!(
+            !engine
+                .drain_events()
+                .any(|event| matches!(event, Event::RiskHit(_)))
+        );
+        assert!(!engine.account(1).unwrap().flagged);
+    }
+}
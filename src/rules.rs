@@ -0,0 +1,610 @@
+//! A small expression language for risk rules, one rule per line:
+//!
+//! ```text
+//! # comments and blank lines are skipped
+//! type == "withdrawal" && amount > 5000 && account.available < 10000 => reject("large")
+//! !(currency == "EUR") || account.locked => flag("review")
+//! ```
+//!
+//! The condition can use the transaction's `type`, `client`, `tx`,
+//! `amount`, `currency` and `destination`, and the balance it applies to as
+//! `account.available`, `account.held`, `account.pending`, `account.total`
+//! and `account.locked`. Missing optional columns are `null`, which only
+//! equals `null`. Strings compared with `type` or `currency` are checked
+//! against the known transaction types and currency codes.
+
+use crate::{Currency, Input, InputType, InvalidCurrency, Output, RiskAction};
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use serde::de::value::{Error as DeError, StrDeserializer};
+use std::cmp::Ordering;
+use std::fmt;
+use std::str::FromStr;
+
+/// A rule written in the rules language: when `condition` holds for a
+/// transaction, the transaction is rejected or its account flagged, and the
+/// hit is reported under `id`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScriptRule {
+    pub id: String,
+    pub action: RiskAction,
+    condition: Expr,
+}
+
+impl ScriptRule {
+    /// Whether the rule fires for `txn`, given the balance it would apply to.
+    pub fn matches(&self, txn: &Input, account: &Output) -> bool {
+        self.condition.eval(txn, account) == Value::Bool(true)
+    }
+}
+
+impl FromStr for ScriptRule {
+    type Err = String;
+
+    fn from_str(line: &str) -> Result<Self, Self::Err> {
+        let mut parser = Parser {
+            tokens: tokenize(line)?,
+            pos: 0,
+        };
+        let condition = parser.expr()?;
+        parser.expect(&Token::Arrow)?;
+        let action = match parser.next() {
+            Some(Token::Ident(name)) if name == "reject" => RiskAction::Reject,
+            Some(Token::Ident(name)) if name == "flag" => RiskAction::Flag,
+            other => {
+                return Err(format!(
+                    "expected reject or flag, found {}",
+                    describe(other)
+                ));
+            }
+        };
+        parser.expect(&Token::LParen)?;
+        let id = match parser.next() {
+            Some(Token::Str(id)) => id.clone(),
+            other => return Err(format!("expected a rule id, found {}", describe(other))),
+        };
+        parser.expect(&Token::RParen)?;
+        if let Some(token) = parser.next() {
+            return Err(format!("unexpected {token} after the action"));
+        }
+        if condition.kind()? != Kind::Bool {
+            return Err("the condition must be true or false".to_string());
+        }
+        Ok(Self {
+            id,
+            action,
+            condition,
+        })
+    }
+}
+
+/// A line of a rules file that doesn't parse or doesn't type check.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RuleError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for RuleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for RuleError {}
+
+/// Parses a whole rules file, stopping at the first invalid line.
+pub fn parse_rules(src: &str) -> Result<Vec<ScriptRule>, RuleError> {
+    src.lines()
+        .enumerate()
+        .map(|(index, line)| (index + 1, line.trim()))
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+        .map(|(line, rule)| rule.parse().map_err(|message| RuleError { line, message }))
+        .collect()
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Number(Decimal),
+    Str(String),
+    Ident(String),
+    Dot,
+    LParen,
+    RParen,
+    And,
+    Or,
+    Not,
+    Arrow,
+    Cmp(CmpOp),
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Number(n) => write!(f, "{n}"),
+            Token::Str(s) => write!(f, "{s:?}"),
+            Token::Ident(name) => f.write_str(name),
+            Token::Dot => f.write_str("."),
+            Token::LParen => f.write_str("("),
+            Token::RParen => f.write_str(")"),
+            Token::And => f.write_str("&&"),
+            Token::Or => f.write_str("||"),
+            Token::Not => f.write_str("!"),
+            Token::Arrow => f.write_str("=>"),
+            Token::Cmp(op) => write!(f, "{op}"),
+        }
+    }
+}
+
+fn describe(token: Option<&Token>) -> String {
+    token.map_or("the end of the rule".to_string(), |token| {
+        format!("{token}")
+    })
+}
+
+fn tokenize(src: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = src.char_indices().peekable();
+    while let Some((start, c)) = chars.next() {
+        let mut next_is = |expected: char| chars.next_if(|&(_, c)| c == expected).is_some();
+        let token = match c {
+            c if c.is_whitespace() => continue,
+            '.' => Token::Dot,
+            '(' => Token::LParen,
+            ')' => Token::RParen,
+            '&' if next_is('&') => Token::And,
+            '|' if next_is('|') => Token::Or,
+            '=' if next_is('=') => Token::Cmp(CmpOp::Eq),
+            '=' if next_is('>') => Token::Arrow,
+            '!' if next_is('=') => Token::Cmp(CmpOp::Ne),
+            '!' => Token::Not,
+            '<' if next_is('=') => Token::Cmp(CmpOp::Le),
+            '<' => Token::Cmp(CmpOp::Lt),
+            '>' if next_is('=') => Token::Cmp(CmpOp::Ge),
+            '>' => Token::Cmp(CmpOp::Gt),
+            '"' => {
+                let mut text = String::new();
+                loop {
+                    match chars.next() {
+                        Some((_, '"')) => break,
+                        Some((_, '\\')) => match chars.next() {
+                            Some((_, c @ ('"' | '\\'))) => text.push(c),
+                            _ => return Err("invalid escape in string".to_string()),
+                        },
+                        Some((_, c)) => text.push(c),
+                        None => return Err("unterminated string".to_string()),
+                    }
+                }
+                Token::Str(text)
+            }
+            c if c.is_ascii_digit() || c == '-' => {
+                let mut end = start + c.len_utf8();
+                while let Some((i, c)) = chars.next_if(|&(_, c)| c.is_ascii_digit() || c == '.') {
+                    end = i + c.len_utf8();
+                }
+                let number = &src[start..end];
+                Token::Number(
+                    Decimal::from_str_exact(number)
+                        .map_err(|_| format!("invalid number {number}"))?,
+                )
+            }
+            c if c.is_ascii_alphabetic() || c == '_' => {
+                let mut end = start + 1;
+                while let Some((i, c)) =
+                    chars.next_if(|&(_, c)| c.is_ascii_alphanumeric() || c == '_')
+                {
+                    end = i + c.len_utf8();
+                }
+                Token::Ident(src[start..end].to_string())
+            }
+            c => return Err(format!("unexpected character {c:?}")),
+        };
+        tokens.push(token);
+    }
+    Ok(tokens)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CmpOp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl CmpOp {
+    fn holds(self, ordering: Ordering) -> bool {
+        match self {
+            CmpOp::Eq => ordering.is_eq(),
+            CmpOp::Ne => ordering.is_ne(),
+            CmpOp::Lt => ordering.is_lt(),
+            CmpOp::Le => ordering.is_le(),
+            CmpOp::Gt => ordering.is_gt(),
+            CmpOp::Ge => ordering.is_ge(),
+        }
+    }
+}
+
+impl fmt::Display for CmpOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            CmpOp::Eq => "==",
+            CmpOp::Ne => "!=",
+            CmpOp::Lt => "<",
+            CmpOp::Le => "<=",
+            CmpOp::Gt => ">",
+            CmpOp::Ge => ">=",
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Field {
+    Type,
+    Client,
+    Tx,
+    Amount,
+    Currency,
+    Destination,
+    Available,
+    Held,
+    Pending,
+    Total,
+    Locked,
+}
+
+impl Field {
+    fn parse(path: &[String]) -> Option<Self> {
+        let path: Vec<_> = path.iter().map(String::as_str).collect();
+        Some(match path[..] {
+            ["type"] => Field::Type,
+            ["client"] => Field::Client,
+            ["tx"] => Field::Tx,
+            ["amount"] => Field::Amount,
+            ["currency"] => Field::Currency,
+            ["destination"] => Field::Destination,
+            ["account", "available"] => Field::Available,
+            ["account", "held"] => Field::Held,
+            ["account", "pending"] => Field::Pending,
+            ["account", "total"] => Field::Total,
+            ["account", "locked"] => Field::Locked,
+            _ => return None,
+        })
+    }
+
+    fn kind(self) -> Kind {
+        match self {
+            Field::Type | Field::Currency => Kind::Text,
+            Field::Locked => Kind::Bool,
+            _ => Kind::Number,
+        }
+    }
+
+    fn value(self, txn: &Input, account: &Output) -> Value {
+        let number = |n: Option<Decimal>| n.map_or(Value::Null, Value::Number);
+        match self {
+            Field::Type => Value::Type(txn.r#type),
+            Field::Client => Value::Number(txn.client.into()),
+            Field::Tx => Value::Number(txn.tx.into()),
+            Field::Amount => number(txn.amount),
+            Field::Currency => txn.currency.map_or(Value::Null, Value::Currency),
+            Field::Destination => number(txn.destination.map(Decimal::from)),
+            Field::Available => Value::Number(account.available),
+            Field::Held => Value::Number(account.held),
+            Field::Pending => Value::Number(account.pending),
+            Field::Total => Value::Number(account.total),
+            Field::Locked => Value::Bool(account.locked),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Number,
+    Text,
+    Bool,
+    Null,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Value {
+    Number(Decimal),
+    Text(String),
+    Bool(bool),
+    /// A transaction type, compared with a string literal naming one.
+    Type(InputType),
+    /// A currency, compared with a string literal holding its code.
+    Currency(Currency),
+    Null,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Expr {
+    Literal(Value),
+    Field(Field),
+    Not(Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Cmp(CmpOp, Box<Expr>, Box<Expr>),
+}
+
+impl Expr {
+    /// Type checks the expression, returning what it evaluates to.
+    fn kind(&self) -> Result<Kind, String> {
+        match self {
+            Expr::Literal(Value::Number(_)) => Ok(Kind::Number),
+            Expr::Literal(Value::Text(_) | Value::Type(_) | Value::Currency(_)) => Ok(Kind::Text),
+            Expr::Literal(Value::Bool(_)) => Ok(Kind::Bool),
+            Expr::Literal(Value::Null) => Ok(Kind::Null),
+            Expr::Field(field) => Ok(field.kind()),
+            Expr::Not(operand) => match operand.kind()? {
+                Kind::Bool => Ok(Kind::Bool),
+                _ => Err("! needs true or false".to_string()),
+            },
+            Expr::And(left, right) | Expr::Or(left, right) => match (left.kind()?, right.kind()?) {
+                (Kind::Bool, Kind::Bool) => Ok(Kind::Bool),
+                _ => Err("&& and || need true or false on both sides".to_string()),
+            },
+            Expr::Cmp(op, left, right) => {
+                let ordered = !matches!(op, CmpOp::Eq | CmpOp::Ne);
+                match (left.kind()?, right.kind()?) {
+                    (Kind::Number, Kind::Number) => Ok(Kind::Bool),
+                    (_, Kind::Null) | (Kind::Null, _) if !ordered => Ok(Kind::Bool),
+                    (l, r) if l == r && !ordered => Ok(Kind::Bool),
+                    (l, r) if l == r => Err(format!("{op} only compares numbers")),
+                    _ => Err(format!("{op} compares values of different kinds")),
+                }
+            }
+        }
+    }
+
+    fn eval(&self, txn: &Input, account: &Output) -> Value {
+        match self {
+            Expr::Literal(value) => value.clone(),
+            Expr::Field(field) => field.value(txn, account),
+            Expr::Not(operand) => Value::Bool(operand.eval(txn, account) != Value::Bool(true)),
+            Expr::And(left, right) => Value::Bool(
+                left.eval(txn, account) == Value::Bool(true)
+                    && right.eval(txn, account) == Value::Bool(true),
+            ),
+            Expr::Or(left, right) => Value::Bool(
+                left.eval(txn, account) == Value::Bool(true)
+                    || right.eval(txn, account) == Value::Bool(true),
+            ),
+            Expr::Cmp(op, left, right) => {
+                let ordering = match (left.eval(txn, account), right.eval(txn, account)) {
+                    (Value::Number(l), Value::Number(r)) => Some(l.cmp(&r)),
+                    (l, r) => (l == r).then_some(Ordering::Equal),
+                };
+                Value::Bool(match (op, ordering) {
+                    (CmpOp::Ne, None) => true,
+                    (_, None) => false,
+                    (op, Some(ordering)) => op.holds(ordering),
+                })
+            }
+        }
+    }
+}
+
+fn parse_type(name: &str) -> Result<InputType, String> {
+    InputType::deserialize(StrDeserializer::<DeError>::new(name))
+        .map_err(|_| format!("unknown transaction type {name:?}"))
+}
+
+/// Reads a string literal compared with `field`: `type` needs one naming a
+/// transaction type, `currency` one holding a currency code.
+fn parse_text(field: Field, text: String) -> Result<Value, String> {
+    match field {
+        Field::Type => parse_type(&text).map(Value::Type),
+        Field::Currency => text
+            .parse()
+            .map(Value::Currency)
+            .map_err(|err: InvalidCurrency| err.to_string()),
+        _ => Ok(Value::Text(text)),
+    }
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<&Token> {
+        let token = self.tokens.get(self.pos);
+        self.pos += 1;
+        token
+    }
+
+    fn eat(&mut self, token: &Token) -> bool {
+        let found = self.peek() == Some(token);
+        self.pos += usize::from(found);
+        found
+    }
+
+    fn expect(&mut self, token: &Token) -> Result<(), String> {
+        if self.eat(token) {
+            return Ok(());
+        }
+        Err(format!("expected {token}, found {}", describe(self.peek())))
+    }
+
+    fn expr(&mut self) -> Result<Expr, String> {
+        let mut left = self.and()?;
+        while self.eat(&Token::Or) {
+            left = Expr::Or(Box::new(left), Box::new(self.and()?));
+        }
+        Ok(left)
+    }
+
+    fn and(&mut self) -> Result<Expr, String> {
+        let mut left = self.not()?;
+        while self.eat(&Token::And) {
+            left = Expr::And(Box::new(left), Box::new(self.not()?));
+        }
+        Ok(left)
+    }
+
+    fn not(&mut self) -> Result<Expr, String> {
+        if self.eat(&Token::Not) {
+            return Ok(Expr::Not(Box::new(self.not()?)));
+        }
+        let left = self.primary()?;
+        let Some(&Token::Cmp(op)) = self.peek() else {
+            return Ok(left);
+        };
+        self.pos += 1;
+        let right = self.primary()?;
+        let (left, right) = match (left, right) {
+            (Expr::Literal(Value::Text(text)), Expr::Field(field)) => {
+                (Expr::Literal(parse_text(field, text)?), Expr::Field(field))
+            }
+            (Expr::Field(field), Expr::Literal(Value::Text(text))) => {
+                (Expr::Field(field), Expr::Literal(parse_text(field, text)?))
+            }
+            operands => operands,
+        };
+        Ok(Expr::Cmp(op, Box::new(left), Box::new(right)))
+    }
+
+    fn primary(&mut self) -> Result<Expr, String> {
+        let token = self.next().cloned();
+        match token {
+            Some(Token::Number(n)) => Ok(Expr::Literal(Value::Number(n))),
+            Some(Token::Str(s)) => Ok(Expr::Literal(Value::Text(s))),
+            Some(Token::LParen) => {
+                let expr = self.expr()?;
+                self.expect(&Token::RParen)?;
+                Ok(expr)
+            }
+            Some(Token::Ident(name)) => match name.as_str() {
+                "true" => Ok(Expr::Literal(Value::Bool(true))),
+                "false" => Ok(Expr::Literal(Value::Bool(false))),
+                "null" => Ok(Expr::Literal(Value::Null)),
+                _ => {
+                    let mut path = vec![name];
+                    while self.eat(&Token::Dot) {
+                        match self.next() {
+                            Some(Token::Ident(name)) => path.push(name.clone()),
+                            other => {
+                                return Err(format!("expected a field, found {}", describe(other)));
+                            }
+                        }
+                    }
+                    let field = Field::parse(&path)
+                        .ok_or_else(|| format!("unknown field {}", path.join(".")))?;
+                    Ok(Expr::Field(field))
+                }
+            },
+            other => Err(format!(
+                "expected a value, found {}",
+                describe(other.as_ref())
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+    use rust_decimal_macros::dec;
+
+    fn withdrawal(amount: Option<Decimal>, currency: Option<&str>) -> Input {
+        Input {
+            currency: currency.map(|code| code.parse().unwrap()),
+            ..Input::new(InputType::Withdrawal, 1, 7, amount)
+        }
+    }
+
+    #[rstest]
+    #[case::spec_example(r#"type == "withdrawal" && amount > 5000 && account.available < 10000 => reject("large")"#, withdrawal(Some(dec!(6000)), None), true)]
+    #[case::below_threshold(r#"type == "withdrawal" && amount > 5000 => reject("large")"#, withdrawal(Some(dec!(5000)), None), false)]
+    #[case::other_type(r#""deposit" == type => flag("deposit")"#, withdrawal(Some(dec!(1)), None), false)]
+    #[case::or(r#"client == 2 || tx >= 7 => flag("r")"#, withdrawal(None, None), true)]
+    #[case::not(
+        r#"!(account.locked) && account.total == 8000 => flag("r")"#,
+        withdrawal(None, None),
+        true
+    )]
+    #[case::precedence(r#"false && false || true => flag("r")"#, withdrawal(None, None), true)]
+    #[case::currency(
+        r#"currency == "EUR" => flag("eur")"#,
+        withdrawal(None, Some("eur")),
+        true
+    )]
+    #[case::currency_any_case(
+        r#""eur" == currency => flag("eur")"#,
+        withdrawal(None, Some("EUR")),
+        true
+    )]
+    #[case::missing_is_null(
+        r#"amount == null && currency != "EUR" => flag("r")"#,
+        withdrawal(None, None),
+        true
+    )]
+    #[case::null_never_ordered(r#"amount < 1 => flag("r")"#, withdrawal(None, None), false)]
+    #[case::negative_literal(r#"account.held > -1.5 => flag("r")"#, withdrawal(None, None), true)]
+    fn test_rule_matches(#[case] src: &str, #[case] txn: Input, #[case] expected: bool) {
+        let rule: ScriptRule = src.parse().unwrap();
+        let account = Output {
+            available: dec!(8000),
+            total: dec!(8000),
+            ..Output::default()
+        };
+        assert_eq!(rule.matches(&txn, &account), expected);
+    }
+
+    #[rstest]
+    #[case::unknown_field(
+        r#"account.balance > 1 => reject("r")"#,
+        "unknown field account.balance"
+    )]
+    #[case::unknown_type(
+        r#"type == "withdrawl" => reject("r")"#,
+        "unknown transaction type \"withdrawl\""
+    )]
+    #[case::unknown_currency(
+        r#"currency == "euro" => reject("r")"#,
+        "invalid currency code \"euro\", expected three letters"
+    )]
+    #[case::mixed_kinds(
+        r#"amount == "5" => reject("r")"#,
+        "== compares values of different kinds"
+    )]
+    #[case::ordered_text(r#"currency > "EUR" => reject("r")"#, "> only compares numbers")]
+    #[case::not_a_condition(r#"amount => reject("r")"#, "the condition must be true or false")]
+    #[case::no_action(r#"amount > 1"#, "expected =>, found the end of the rule")]
+    #[case::unknown_action(r#"amount > 1 => block("r")"#, "expected reject or flag, found block")]
+    #[case::no_id(r#"amount > 1 => reject()"#, "expected a rule id, found )")]
+    #[case::trailing(r#"amount > 1 => reject("r") x"#, "unexpected x after the action")]
+    #[case::unterminated(r#"amount > 1 => reject("r)"#, "unterminated string")]
+    #[case::stray_character(r#"amount = 1 => reject("r")"#, "unexpected character '='")]
+    fn test_invalid_rules(#[case] src: &str, #[case] expected: &str) {
+        assert_eq!(src.parse::<ScriptRule>(), Err(expected.to_string()));
+    }
+
+    #[test]
+    fn test_parse_rules_file() {
+        let src = "# comment\n\namount > 1 => flag(\"a\")\n  amount > 2 => reject(\"b\")\n";
+        let rules = parse_rules(src).unwrap();
+        let ids: Vec<_> = rules
+            .iter()
+            .map(|rule| (rule.id.as_str(), rule.action))
+            .collect();
+        assert_eq!(ids, [("a", RiskAction::Flag), ("b", RiskAction::Reject)]);
+        assert_eq!(
+            parse_rules("amount > 1 => flag(\"a\")\n\namount >"),
+            Err(RuleError {
+                line: 3,
+                message: "expected a value, found the end of the rule".to_string(),
+            })
+        );
+    }
+}
@@ -0,0 +1,34 @@
+use rust_decimal::Decimal;
+
+/// A rule that applies to amounts of at least [`Tier::threshold`].
+pub(crate) trait Tier {
+    fn threshold(&self) -> Decimal;
+}
+
+/// Rules ordered by their threshold, of which the highest one an amount
+/// reaches applies. Shared by the fee schedule and the funds availability
+/// policy.
+#[derive(Debug, Clone)]
+pub(crate) struct Tiers<T>(Vec<T>);
+
+impl<T> Default for Tiers<T> {
+    fn default() -> Self {
+        Self(Vec::new())
+    }
+}
+
+impl<T: Tier> Tiers<T> {
+    /// Adds a rule, replacing any earlier one with the same threshold.
+    pub(crate) fn insert(&mut self, rule: T) {
+        match self.0.binary_search_by_key(&rule.threshold(), T::threshold) {
+            Ok(index) => self.0[index] = rule,
+            Err(index) => self.0.insert(index, rule),
+        }
+    }
+
+    /// The highest tier `amount` reaches, if it reaches any.
+    pub(crate) fn get(&self, amount: Decimal) -> Option<&T> {
+        let reached = self.0.partition_point(|tier| tier.threshold() <= amount);
+        self.0.get(reached.checked_sub(1)?)
+    }
+}